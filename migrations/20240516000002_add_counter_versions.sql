@@ -0,0 +1,4 @@
+-- Add a version to every counter for optimistic concurrency control.
+-- Counters start at version 1 and the application bumps the version on every write,
+-- so version 0 can stand for "counter does not exist" in compare-and-set requests.
+ALTER TABLE counters ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,6 @@
+-- Add a per-counter policy deciding what happens when an increment overflows:
+--   'error'    - reject the increment (the default)
+--   'saturate' - clamp the value to the 64-bit integer limits
+--   'wrap'     - wrap around using two's complement arithmetic
+ALTER TABLE counters ADD COLUMN overflow_policy TEXT NOT NULL DEFAULT 'error'
+    CHECK (overflow_policy IN ('error', 'saturate', 'wrap'));
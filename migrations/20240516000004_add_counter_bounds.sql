@@ -0,0 +1,5 @@
+-- Add optional lower and upper limits for a counter's value.
+-- NULL means the counter is unbounded in that direction. The application sets the
+-- bounds when the counter is created and rejects any change that would break them.
+ALTER TABLE counters ADD COLUMN min_value INTEGER;
+ALTER TABLE counters ADD COLUMN max_value INTEGER;
@@ -0,0 +1,20 @@
+-- Record every change made to a counter so its value can be audited later.
+--   kind        - 'increment', 'set' or 'delete'
+--   amount      - the amount added by an increment (NULL for sets and deletes)
+--   value       - the counter's value after the change (NULL for deletes)
+--   reason      - optional free-text reason supplied by the caller
+--   occurred_at - time of the change in milliseconds since the Unix epoch
+-- Events are kept when their counter is deleted, so a deleted counter's history
+-- stays available and a re-created counter continues it.
+CREATE TABLE IF NOT EXISTS counter_events (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    counter_id TEXT NOT NULL,
+    kind TEXT NOT NULL CHECK (kind IN ('increment', 'set', 'delete')),
+    amount INTEGER,
+    value INTEGER,
+    reason TEXT,
+    occurred_at INTEGER NOT NULL DEFAULT (CAST(unixepoch('subsec') * 1000 AS INTEGER))
+);
+
+CREATE INDEX IF NOT EXISTS idx_counter_events_counter_time
+    ON counter_events(counter_id, occurred_at, id);
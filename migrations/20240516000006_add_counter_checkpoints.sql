@@ -0,0 +1,36 @@
+-- Turn counter_events into an append-only log that point-in-time reads can trust.
+CREATE TRIGGER IF NOT EXISTS counter_events_no_update
+BEFORE UPDATE ON counter_events
+BEGIN
+    SELECT RAISE(ABORT, 'counter_events is append-only');
+END;
+
+CREATE TRIGGER IF NOT EXISTS counter_events_no_delete
+BEFORE DELETE ON counter_events
+BEGIN
+    SELECT RAISE(ABORT, 'counter_events is append-only');
+END;
+
+-- Counters created before the log existed get a single 'set' event for the value
+-- they had when they were last updated, so they can be read as of that time.
+INSERT INTO counter_events (counter_id, kind, value, reason, occurred_at)
+SELECT id, 'set', value, 'history back-fill', CAST(strftime('%s', updated_at) AS INTEGER) * 1000
+FROM counters
+WHERE id NOT IN (SELECT counter_id FROM counter_events)
+ORDER BY updated_at, id;
+
+-- Snapshots of a counter's value taken every CHECKPOINT_INTERVAL events, so reading
+-- a past value only replays the events recorded after the closest checkpoint.
+--   event_id    - the last event included in the snapshot
+--   value       - the counter's value after that event (NULL if it was deleted)
+--   occurred_at - time of that event in milliseconds since the Unix epoch
+CREATE TABLE IF NOT EXISTS counter_checkpoints (
+    counter_id TEXT NOT NULL,
+    event_id INTEGER NOT NULL,
+    value INTEGER,
+    occurred_at INTEGER NOT NULL,
+    PRIMARY KEY (counter_id, event_id)
+);
+
+CREATE INDEX IF NOT EXISTS idx_counter_events_counter_id
+    ON counter_events(counter_id, id);
@@ -0,0 +1,28 @@
+-- Per-bucket totals of the increments applied to each counter, for trend charts.
+--   granularity  - 'minute', 'hour' or 'day'
+--   bucket_start - start of the bucket in milliseconds since the Unix epoch (UTC)
+--   total        - sum of the amounts the counter was incremented by in the bucket
+--   increments   - number of increments applied in the bucket
+CREATE TABLE IF NOT EXISTS counter_rollups (
+    counter_id TEXT NOT NULL,
+    granularity TEXT NOT NULL CHECK (granularity IN ('minute', 'hour', 'day')),
+    bucket_start INTEGER NOT NULL,
+    total INTEGER NOT NULL DEFAULT 0,
+    increments INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (counter_id, granularity, bucket_start)
+);
+
+-- Build the rollups for increments recorded before this table existed. Totals are
+-- summed as REAL and clamped, as SUM() fails on 64-bit overflow.
+INSERT INTO counter_rollups (counter_id, granularity, bucket_start, total, increments)
+SELECT counter_id, granularity.name, occurred_at - occurred_at % granularity.millis,
+       CAST(MAX(MIN(TOTAL(amount), 9223372036854775807), -9223372036854775808) AS INTEGER),
+       COUNT(*)
+FROM counter_events
+JOIN (
+    SELECT 'minute' AS name, 60000 AS millis
+    UNION ALL SELECT 'hour', 3600000
+    UNION ALL SELECT 'day', 86400000
+) AS granularity
+WHERE kind = 'increment'
+GROUP BY counter_id, granularity.name, occurred_at - occurred_at % granularity.millis;
@@ -0,0 +1,113 @@
+-- Track decreases as well as increases of a counter's value.
+--   total_decrements  - number of times the value went down
+--   lowest_value      - lowest value the counter has reached (counters start from 0)
+--   last_increment_at - last time the value went up, in ms since the Unix epoch
+--   last_decrement_at - last time the value went down, in ms since the Unix epoch
+--   increment_rate    - exponentially weighted increments per minute as of rate_updated_at,
+--                       maintained by the application on every positive increment
+ALTER TABLE counters ADD COLUMN total_decrements INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE counters ADD COLUMN lowest_value INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE counters ADD COLUMN last_increment_at INTEGER;
+ALTER TABLE counters ADD COLUMN last_decrement_at INTEGER;
+ALTER TABLE counters ADD COLUMN increment_rate REAL NOT NULL DEFAULT 0.0;
+ALTER TABLE counters ADD COLUMN rate_updated_at INTEGER;
+
+-- The old trigger only saw the value going up; replace it with one per direction
+DROP TRIGGER IF EXISTS update_counter_stats;
+
+CREATE TRIGGER IF NOT EXISTS update_counter_stats_on_increase
+AFTER UPDATE OF value ON counters
+WHEN NEW.value > OLD.value
+BEGIN
+    UPDATE counters SET
+        total_increments = total_increments + 1,
+        average_increment = (OLD.average_increment * OLD.total_increments + (NEW.value - OLD.value)) / (OLD.total_increments + 1),
+        highest_value = MAX(highest_value, NEW.value),
+        last_increment_at = CAST(unixepoch('subsec') * 1000 AS INTEGER)
+    WHERE id = NEW.id;
+END;
+
+CREATE TRIGGER IF NOT EXISTS update_counter_stats_on_decrease
+AFTER UPDATE OF value ON counters
+WHEN NEW.value < OLD.value
+BEGIN
+    UPDATE counters SET
+        total_decrements = total_decrements + 1,
+        lowest_value = MIN(lowest_value, NEW.value),
+        last_decrement_at = CAST(unixepoch('subsec') * 1000 AS INTEGER)
+    WHERE id = NEW.id;
+END;
+
+-- A counter created with a non-zero value has already reached that value
+CREATE TRIGGER IF NOT EXISTS init_counter_stats
+AFTER INSERT ON counters
+BEGIN
+    UPDATE counters SET
+        lowest_value = MIN(lowest_value, NEW.value),
+        highest_value = MAX(highest_value, NEW.value)
+    WHERE id = NEW.id;
+END;
+
+-- Back-fill the new columns from the history recorded since each counter was last
+-- (re)created. The rate is approximated by the increments of the last five minutes.
+-- The timestamp trigger is suspended so the back-fill doesn't touch updated_at.
+DROP TRIGGER IF EXISTS update_counters_timestamp;
+
+WITH live_events AS (
+    SELECT e.counter_id, e.value, e.occurred_at,
+           LAG(e.value) OVER (PARTITION BY e.counter_id ORDER BY e.id) AS previous
+    FROM counter_events e
+    WHERE e.id > COALESCE(
+        (SELECT MAX(d.id) FROM counter_events d WHERE d.counter_id = e.counter_id AND d.kind = 'delete'),
+        0
+    )
+),
+summary AS (
+    SELECT counter_id,
+           COALESCE(SUM(value < previous), 0) AS decrements,
+           MIN(value) AS lowest,
+           MAX(CASE WHEN value > previous THEN occurred_at END) AS last_increment_at,
+           MAX(CASE WHEN value < previous THEN occurred_at END) AS last_decrement_at,
+           COALESCE(SUM(value > previous
+                         AND occurred_at >= CAST(unixepoch('subsec') * 1000 AS INTEGER) - 300000), 0) AS recent
+    FROM live_events
+    GROUP BY counter_id
+)
+UPDATE counters SET
+    total_decrements = summary.decrements,
+    lowest_value = MIN(0, counters.value, COALESCE(summary.lowest, counters.value)),
+    last_increment_at = summary.last_increment_at,
+    last_decrement_at = summary.last_decrement_at,
+    increment_rate = summary.recent / 5.0,
+    rate_updated_at = CASE WHEN summary.recent > 0 THEN CAST(unixepoch('subsec') * 1000 AS INTEGER) END
+FROM summary
+WHERE counters.id = summary.counter_id;
+
+UPDATE counters SET lowest_value = MIN(0, value) WHERE id NOT IN (SELECT counter_id FROM counter_events);
+
+CREATE TRIGGER IF NOT EXISTS update_counters_timestamp
+AFTER UPDATE ON counters
+BEGIN
+    UPDATE counters SET updated_at = CURRENT_TIMESTAMP WHERE id = NEW.id;
+END;
+
+-- Expose the new statistics through the view
+DROP VIEW IF EXISTS counter_stats;
+
+CREATE VIEW counter_stats AS
+SELECT
+    id,
+    value AS current_value,
+    total_increments,
+    average_increment,
+    highest_value,
+    total_decrements,
+    lowest_value,
+    last_increment_at,
+    last_decrement_at,
+    increment_rate,
+    rate_updated_at,
+    created_at,
+    updated_at,
+    description
+FROM counters;
@@ -0,0 +1,40 @@
+-- Histogram of the amounts each counter has been incremented by (positive amounts only).
+-- Buckets are powers of two: bucket b counts amounts from 2^(b-1) to 2^b - 1, so
+-- bucket 1 holds amounts of 1, bucket 2 amounts of 2-3, bucket 3 amounts of 4-7 and so
+-- on up to bucket 63. A counter's histogram is removed when the counter is deleted.
+CREATE TABLE IF NOT EXISTS counter_histograms (
+    counter_id TEXT NOT NULL,
+    bucket INTEGER NOT NULL CHECK (bucket BETWEEN 1 AND 63),
+    count INTEGER NOT NULL DEFAULT 0,
+    PRIMARY KEY (counter_id, bucket)
+);
+
+-- Build the histograms from the increments recorded since each counter was last
+-- (re)created. An amount's bucket is its bit length: the number of powers of two
+-- that are not larger than it.
+WITH RECURSIVE powers(value) AS (
+    SELECT 1
+    UNION ALL
+    SELECT value * 2 FROM powers WHERE value < 4611686018427387904
+),
+live_increments AS (
+    SELECT e.id, e.counter_id, e.amount
+    FROM counter_events e
+    WHERE e.kind = 'increment'
+      AND e.amount > 0
+      AND e.counter_id IN (SELECT id FROM counters)
+      AND e.id > COALESCE(
+          (SELECT MAX(d.id) FROM counter_events d WHERE d.counter_id = e.counter_id AND d.kind = 'delete'),
+          0
+      )
+),
+bucketed AS (
+    SELECT i.counter_id, COUNT(*) AS bucket
+    FROM live_increments i
+    JOIN powers p ON i.amount >= p.value
+    GROUP BY i.id
+)
+INSERT INTO counter_histograms (counter_id, bucket, count)
+SELECT counter_id, bucket, COUNT(*)
+FROM bucketed
+GROUP BY counter_id, bucket;
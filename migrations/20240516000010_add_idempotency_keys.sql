@@ -0,0 +1,19 @@
+-- Results of increments sent with an idempotency key, so a retried request
+-- returns the original result instead of incrementing the counter again.
+--   key        - idempotency key chosen by the client
+--   counter_id - counter the increment was applied to
+--   amount     - amount the counter was incremented by
+--   value      - value of the counter after the increment
+--   version    - version of the counter after the increment
+--   created_at - when the increment was applied, in milliseconds since the Unix epoch
+-- Keys are forgotten once they are older than the server's retention window.
+CREATE TABLE IF NOT EXISTS idempotency_keys (
+    key TEXT PRIMARY KEY,
+    counter_id TEXT NOT NULL,
+    amount INTEGER NOT NULL,
+    value INTEGER NOT NULL,
+    version INTEGER NOT NULL,
+    created_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_idempotency_keys_created_at ON idempotency_keys (created_at);
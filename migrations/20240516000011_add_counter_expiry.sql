@@ -0,0 +1,27 @@
+-- Optional expiry time of a counter, in milliseconds since the Unix epoch. Expired
+-- counters read as absent and are deleted by the server's expiry reaper.
+ALTER TABLE counters ADD COLUMN expires_at INTEGER;
+
+CREATE INDEX IF NOT EXISTS idx_counters_expires_at ON counters (expires_at) WHERE expires_at IS NOT NULL;
+
+-- Expose the expiry time through the stats view, so stats reads can skip expired counters
+DROP VIEW IF EXISTS counter_stats;
+
+CREATE VIEW counter_stats AS
+SELECT
+    id,
+    value AS current_value,
+    total_increments,
+    average_increment,
+    highest_value,
+    total_decrements,
+    lowest_value,
+    last_increment_at,
+    last_decrement_at,
+    increment_rate,
+    rate_updated_at,
+    created_at,
+    updated_at,
+    description,
+    expires_at
+FROM counters;
@@ -0,0 +1,11 @@
+-- State of the rate limits served by RateLimitService, using the generic cell rate
+-- algorithm (GCRA). Each key only needs its theoretical arrival time: the time,
+-- in microseconds since the Unix epoch, at which its limit would be fully
+-- replenished. A key whose theoretical arrival time has passed behaves exactly
+-- like a key without a row, so such rows are purged in the background.
+CREATE TABLE IF NOT EXISTS rate_limits (
+    key TEXT PRIMARY KEY,
+    theoretical_arrival INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_rate_limits_theoretical_arrival ON rate_limits (theoretical_arrival);
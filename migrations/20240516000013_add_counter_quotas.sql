@@ -0,0 +1,8 @@
+-- Quotas: a hard ceiling that increments may not take a counter past.
+--   quota_limit        - highest value increments may take the counter to (no quota if NULL)
+--   quota_period       - 'day', 'week' or 'month' (UTC) after which the counter is reset to
+--                        zero; NULL if the quota never resets
+--   quota_period_start - start of the current quota period in milliseconds since the Unix epoch
+ALTER TABLE counters ADD COLUMN quota_limit INTEGER;
+ALTER TABLE counters ADD COLUMN quota_period TEXT CHECK (quota_period IN ('day', 'week', 'month'));
+ALTER TABLE counters ADD COLUMN quota_period_start INTEGER;
@@ -0,0 +1,32 @@
+-- Cron-like schedules on which the server's reset scheduler resets counters.
+--   counter_id    - counter the schedule resets
+--   schedule      - five field cron expression or shorthand such as '@daily', in UTC
+--   reset_value   - value the counter is reset to
+--   period_start  - start of the current period in milliseconds since the Unix epoch
+--   next_reset_at - end of the current period, when the next reset is due
+-- Resets whose time passed while the server was down are applied when it starts.
+CREATE TABLE IF NOT EXISTS counter_reset_schedules (
+    counter_id TEXT PRIMARY KEY,
+    schedule TEXT NOT NULL,
+    reset_value INTEGER NOT NULL DEFAULT 0,
+    period_start INTEGER NOT NULL,
+    next_reset_at INTEGER NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS idx_counter_reset_schedules_next_reset_at ON counter_reset_schedules (next_reset_at);
+
+-- Final value of each period ended by a scheduled reset. Like the history in
+-- counter_events, the archive outlives the counter itself.
+--   period_start - start of the period in milliseconds since the Unix epoch
+--   period_end   - scheduled time of the reset that ended the period
+--   final_value  - value of the counter when the period ended
+--   reset_at     - when the reset was actually applied, later than period_end
+--                  if the server was down at the scheduled time
+CREATE TABLE IF NOT EXISTS counter_periods (
+    counter_id TEXT NOT NULL,
+    period_start INTEGER NOT NULL,
+    period_end INTEGER NOT NULL,
+    final_value INTEGER NOT NULL,
+    reset_at INTEGER NOT NULL,
+    PRIMARY KEY (counter_id, period_end)
+);
@@ -0,0 +1,9 @@
+-- Sequences handing out unique IDs in contiguous blocks through NextIds.
+--   name    - name of the sequence, chosen by the client
+--   next_id - first ID not handed out yet; sequences start at 1
+-- next_id only ever grows, so an ID is never handed out twice, even across
+-- restarts. Sequences are kept apart from counters, which can be reset or deleted.
+CREATE TABLE IF NOT EXISTS sequences (
+    name TEXT PRIMARY KEY,
+    next_id INTEGER NOT NULL
+);
@@ -0,0 +1,16 @@
+-- Last version of every deleted counter, so a counter recreated under the same
+-- ID carries on from it instead of starting again at version 1. Otherwise a
+-- compare-and-set holding a version of the deleted counter could succeed
+-- against the new one.
+CREATE TABLE IF NOT EXISTS counter_tombstones (
+    counter_id TEXT PRIMARY KEY,
+    version INTEGER NOT NULL
+);
+
+-- Every write since the history was added records one event and bumps the
+-- version by at most one, so the number of events of a deleted counter is at
+-- least the last version it had.
+INSERT OR IGNORE INTO counter_tombstones (counter_id, version)
+SELECT counter_id, COUNT(*) FROM counter_events
+WHERE counter_id NOT IN (SELECT id FROM counters)
+GROUP BY counter_id;
@@ -0,0 +1,11 @@
+-- Number of events recorded for each counter since its last checkpoint, so
+-- deciding whether to take a checkpoint does not count the history on every write.
+CREATE TABLE IF NOT EXISTS counter_checkpoint_progress (
+    counter_id TEXT PRIMARY KEY,
+    events_since_checkpoint INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO counter_checkpoint_progress (counter_id, events_since_checkpoint)
+SELECT e.counter_id, COUNT(*) FROM counter_events e
+WHERE e.id > COALESCE((SELECT MAX(c.event_id) FROM counter_checkpoints c WHERE c.counter_id = e.counter_id), 0)
+GROUP BY e.counter_id;
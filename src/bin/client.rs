@@ -3,16 +3,19 @@
 //! A client for testing the HelloService gRPC server with SQLite migrations.
 //! This client connects to the server and tests all available RPC methods.
 
+use agentic_protos::hello_service::hello_service_client::HelloServiceClient;
+use agentic_protos::hello_service::rate_limit_service_client::RateLimitServiceClient;
+use agentic_protos::hello_service::{
+    HelloRequest, IncrementCounterRequest, GetCounterRequest, ListCountersRequest,
+    CounterSortOrder, GetCounterStatsRequest, StreamIncrementsRequest, CompareAndSetRequest,
+    ApplyBatchRequest, CounterOperation, counter_operation::Operation, GetCounterHistoryRequest,
+    GetCounterSeriesRequest, SeriesGranularity, TryAcquireRequest, SetQuotaRequest, QuotaPeriod,
+    SetResetScheduleRequest, GetCounterPeriodsRequest,
+};
+use agentic_protos::id_allocator::IdAllocator;
 use anyhow::Result;
-use hello_service::hello_service_client::HelloServiceClient;
-use hello_service::{HelloRequest, IncrementCounterRequest, GetCounterRequest};
 use tokio::time::{sleep, Duration};
 
-// Import the generated protobuf code
-pub mod hello_service {
-    tonic::include_proto!("hello_service");
-}
-
 /// Main function to run the gRPC client.
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -22,8 +25,9 @@ async fn main() -> Result<()> {
         .connect()
         .await?;
 
-    // Create a client using the channel
-    let mut client = HelloServiceClient::new(channel);
+    // Create clients for both services sharing the channel
+    let mut client = HelloServiceClient::new(channel.clone());
+    let mut rate_limiter = RateLimitServiceClient::new(channel);
     println!("✅ Connection established successfully");
 
     // Test 1: SayHello RPC
@@ -43,7 +47,11 @@ async fn main() -> Result<()> {
 
     // Test 2: Get initial counter value
     println!("\n=== Testing GetCounter RPC (initial value) ===");
-    let request = tonic::Request::new(GetCounterRequest {});
+    let request = tonic::Request::new(GetCounterRequest {
+        counter_id: String::new(),
+        create_if_missing: false,
+        as_of: None,
+    });
 
     match client.get_counter(request).await {
         Ok(response) => {
@@ -66,6 +74,11 @@ async fn main() -> Result<()> {
     for increment in increments {
         let request = tonic::Request::new(IncrementCounterRequest {
             increment_by: increment,
+            increment_by_int32: 0,
+            counter_id: String::new(),
+            reason: Some(format!("client demo increment by {}", increment)),
+            idempotency_key: None,
+            expires_at: None,
         });
 
         match client.increment_counter(request).await {
@@ -85,20 +98,372 @@ async fn main() -> Result<()> {
 
     // Test 4: Get final counter value
     println!("\n=== Testing GetCounter RPC (final value) ===");
-    let request = tonic::Request::new(GetCounterRequest {});
+    let request = tonic::Request::new(GetCounterRequest {
+        counter_id: String::new(),
+        create_if_missing: false,
+        as_of: None,
+    });
 
     match client.get_counter(request).await {
         Ok(response) => {
-            let value = response.into_inner().value;
-            println!("✅ Final counter value: {}", value);
+            let counter = response.into_inner();
+            println!("✅ Final counter value: {} (version {})", counter.value, counter.version);
             println!("   This value is stored in a SQLite database file");
             println!("   The database now tracks statistics like total increments and average!");
+
+            // Double the counter with a read-modify-write cycle guarded by its version
+            let request = tonic::Request::new(CompareAndSetRequest {
+                counter_id: String::new(),
+                expected_version: counter.version,
+                new_value: counter.value.saturating_mul(2),
+                new_value_int32: 0,
+            });
+            match client.compare_and_set(request).await {
+                Ok(response) => {
+                    let counter = response.into_inner();
+                    println!("✅ Counter doubled to {} (version {})", counter.value, counter.version);
+                },
+                Err(err) if err.code() == tonic::Code::Aborted => {
+                    println!("⚠️  Counter changed concurrently, not doubled: {}", err.message());
+                },
+                Err(err) => {
+                    println!("❌ CompareAndSet failed: {}", err);
+                }
+            }
         },
         Err(err) => {
             println!("❌ GetCounter failed: {}", err);
         }
     }
 
+    // Test 5: Stream a burst of increments to a few named counters
+    println!("\n=== Testing StreamIncrements RPC ===");
+    let increments: Vec<StreamIncrementsRequest> = (0..100)
+        .map(|i| StreamIncrementsRequest {
+            counter_id: format!("client.stream.{}", i % 3),
+            amount: 1,
+            amount_int32: 0,
+        })
+        .collect();
+
+    match client.stream_increments(tokio_stream::iter(increments)).await {
+        Ok(response) => {
+            let summary = response.into_inner();
+            println!("✅ Applied {} streamed increments", summary.applied_increments);
+            for counter in summary.counters {
+                println!("   {}: {}", counter.counter_id, counter.value);
+            }
+        },
+        Err(err) => {
+            println!("❌ StreamIncrements failed: {}", err);
+        }
+    }
+
+    // Test 6: Bump related counters together in one atomic batch
+    println!("\n=== Testing ApplyBatch RPC ===");
+    let request = tonic::Request::new(ApplyBatchRequest {
+        operations: ["client.orders", "client.orders.eu"]
+            .into_iter()
+            .map(|counter_id| CounterOperation {
+                counter_id: counter_id.into(),
+                operation: Some(Operation::IncrementBy(1)),
+            })
+            .collect(),
+    });
+
+    match client.apply_batch(request).await {
+        Ok(response) => {
+            for result in response.into_inner().results {
+                println!("✅ {} is now {} (version {})", result.counter_id, result.value, result.version);
+            }
+        },
+        Err(err) => {
+            println!("❌ ApplyBatch failed: {}", err);
+        }
+    }
+
+    // Test 7: Get statistics for the main counter
+    println!("\n=== Testing GetCounterStats RPC ===");
+    let request = tonic::Request::new(GetCounterStatsRequest {
+        counter_id: String::new(),
+    });
+
+    match client.get_counter_stats(request).await {
+        Ok(response) => {
+            let stats = response.into_inner();
+            println!("✅ Counter '{}' statistics:", stats.counter_id);
+            println!("   value={}, increments={}, avg={:.2}, highest={}",
+                stats.value, stats.total_increments, stats.average_increment, stats.highest_value);
+            println!("   decrements={}, lowest={}, rate={:.2} increments/min",
+                stats.total_decrements, stats.lowest_value, stats.increments_per_minute);
+            if let (Some(p50), Some(p90), Some(p99)) = (stats.p50_increment, stats.p90_increment, stats.p99_increment) {
+                println!("   increment amounts: p50={}, p90={}, p99={}", p50, p90, p99);
+            }
+            for bucket in &stats.increment_histogram {
+                println!("   [{}, {}]: {} increments", bucket.lower_bound, bucket.upper_bound, bucket.count);
+            }
+            if let Some(updated_at) = stats.updated_at {
+                println!("   last updated at {}s since the Unix epoch", updated_at.seconds);
+            }
+        },
+        Err(err) => {
+            println!("❌ GetCounterStats failed: {}", err);
+        }
+    }
+
+    // Test 8: List all counters, largest first, a page at a time
+    println!("\n=== Testing ListCounters RPC ===");
+    let mut page_token = String::new();
+    loop {
+        let request = tonic::Request::new(ListCountersRequest {
+            prefix: String::new(),
+            order_by: CounterSortOrder::Value as i32,
+            descending: true,
+            page_size: 10,
+            page_token,
+        });
+
+        match client.list_counters(request).await {
+            Ok(response) => {
+                let response = response.into_inner();
+                for counter in &response.counters {
+                    println!("✅ {}: {}", counter.counter_id, counter.value);
+                }
+                if response.next_page_token.is_empty() {
+                    break;
+                }
+                page_token = response.next_page_token;
+            },
+            Err(err) => {
+                println!("❌ ListCounters failed: {}", err);
+                break;
+            }
+        }
+    }
+
+    // Test 9: Show the most recent page of the main counter's history
+    println!("\n=== Testing GetCounterHistory RPC ===");
+    let request = tonic::Request::new(GetCounterHistoryRequest {
+        counter_id: String::new(),
+        page_size: 10,
+        ..Default::default()
+    });
+
+    match client.get_counter_history(request).await {
+        Ok(response) => {
+            for event in response.into_inner().events {
+                println!("✅ #{} {:?} amount={:?} value={:?} reason={:?}",
+                    event.event_id, event.kind(), event.amount, event.value, event.reason);
+            }
+        },
+        Err(err) => {
+            println!("❌ GetCounterHistory failed: {}", err);
+        }
+    }
+
+    // Test 10: Read the main counter as it was a minute ago
+    println!("\n=== Testing GetCounter RPC with as_of ===");
+    let minute_ago = std::time::SystemTime::now() - Duration::from_secs(60);
+    let request = tonic::Request::new(GetCounterRequest {
+        counter_id: String::new(),
+        create_if_missing: false,
+        as_of: Some(minute_ago.into()),
+    });
+
+    match client.get_counter(request).await {
+        Ok(response) => {
+            println!("✅ Counter value a minute ago: {}", response.into_inner().value);
+        },
+        Err(err) => {
+            println!("❌ GetCounter as_of failed: {}", err);
+        }
+    }
+
+    // Test 11: Per-minute increment totals of the main counter over the last five minutes
+    println!("\n=== Testing GetCounterSeries RPC ===");
+    let now = std::time::SystemTime::now();
+    let request = tonic::Request::new(GetCounterSeriesRequest {
+        counter_id: String::new(),
+        granularity: SeriesGranularity::Minute as i32,
+        start_time: Some((now - Duration::from_secs(5 * 60)).into()),
+        end_time: Some(now.into()),
+    });
+
+    match client.get_counter_series(request).await {
+        Ok(response) => {
+            for bucket in response.into_inner().buckets {
+                let start = bucket.start_time.unwrap_or_default().seconds;
+                println!("✅ minute starting at {}s: total={}, increments={}",
+                    start, bucket.total, bucket.increments);
+            }
+        },
+        Err(err) => {
+            println!("❌ GetCounterSeries failed: {}", err);
+        }
+    }
+
+    // Test 12: Retry an increment with the same idempotency key; it is only applied once
+    println!("\n=== Testing IncrementCounter RPC with an idempotency key ===");
+    let since_epoch = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    let idempotency_key = format!("client-demo-{}", since_epoch.as_nanos());
+
+    for attempt in 1..=2 {
+        let request = tonic::Request::new(IncrementCounterRequest {
+            increment_by: 1,
+            increment_by_int32: 0,
+            counter_id: "idempotent_demo".into(),
+            reason: None,
+            idempotency_key: Some(idempotency_key.clone()),
+            expires_at: None,
+        });
+
+        match client.increment_counter(request).await {
+            Ok(response) => {
+                let counter = response.into_inner();
+                println!("✅ attempt {}: value={} (version {})", attempt, counter.value, counter.version);
+            },
+            Err(err) => {
+                println!("❌ IncrementCounter failed: {}", err);
+            }
+        }
+    }
+
+    // Test 13: Acquire permits until a rate limit of 3 per 10 seconds denies the request
+    println!("\n=== Testing TryAcquire RPC ===");
+    for attempt in 1..=4 {
+        let request = tonic::Request::new(TryAcquireRequest {
+            key: "client_demo".into(),
+            permits: 1,
+            limit: 3,
+            window: Some(Duration::from_secs(10).try_into()?),
+        });
+
+        match rate_limiter.try_acquire(request).await {
+            Ok(response) => {
+                let decision = response.into_inner();
+                let retry_after = decision.retry_after.unwrap_or_default();
+                println!("✅ attempt {}: allowed={}, remaining={}, retry after {}.{:03}s",
+                    attempt, decision.allowed, decision.remaining,
+                    retry_after.seconds, retry_after.nanos / 1_000_000);
+            },
+            Err(err) => {
+                println!("❌ TryAcquire failed: {}", err);
+                break;
+            }
+        }
+    }
+
+    // Test 14: Allow two more increments today, then watch the quota reject the third
+    println!("\n=== Testing SetQuota RPC ===");
+    let increment = || tonic::Request::new(IncrementCounterRequest {
+        increment_by: 1,
+        increment_by_int32: 0,
+        counter_id: "quota_demo".into(),
+        reason: None,
+        idempotency_key: None,
+        expires_at: None,
+    });
+    let current = tonic::Request::new(IncrementCounterRequest { increment_by: 0, ..increment().into_inner() });
+
+    match client.increment_counter(current).await {
+        Ok(response) => {
+            let request = tonic::Request::new(SetQuotaRequest {
+                counter_id: "quota_demo".into(),
+                limit: Some(response.into_inner().value.saturating_add(2)),
+                period: QuotaPeriod::Day as i32,
+            });
+            if let Err(err) = client.set_quota(request).await {
+                println!("❌ SetQuota failed: {}", err);
+            }
+            for attempt in 1..=3 {
+                match client.increment_counter(increment()).await {
+                    Ok(response) => println!("✅ attempt {}: value={}", attempt, response.into_inner().value),
+                    Err(err) if err.code() == tonic::Code::ResourceExhausted => {
+                        println!("✅ attempt {}: rejected by the quota: {}", attempt, err.message());
+                    },
+                    Err(err) => println!("❌ IncrementCounter failed: {}", err),
+                }
+            }
+        },
+        Err(err) => {
+            println!("❌ IncrementCounter failed: {}", err);
+        }
+    }
+
+    // Test 15: Reset a demo counter every day at midnight UTC, list its archived periods and
+    // remove the schedule again, so the demo leaves no resets behind
+    println!("\n=== Testing SetResetSchedule RPC ===");
+    let schedule_demo = "schedule_demo";
+    let request = tonic::Request::new(IncrementCounterRequest {
+        increment_by: 1,
+        increment_by_int32: 0,
+        counter_id: schedule_demo.into(),
+        reason: None,
+        idempotency_key: None,
+        expires_at: None,
+    });
+    if let Err(err) = client.increment_counter(request).await {
+        println!("❌ Creating the schedule demo counter failed: {}", err);
+    }
+    let request = tonic::Request::new(SetResetScheduleRequest {
+        counter_id: schedule_demo.into(),
+        schedule: "@daily".into(),
+        reset_value: 0,
+    });
+    match client.set_reset_schedule(request).await {
+        Ok(response) => {
+            if let Some(next_reset_at) = response.into_inner().next_reset_at {
+                println!("✅ Demo counter resets next at {}s since the epoch", next_reset_at.seconds);
+            }
+            let request = tonic::Request::new(GetCounterPeriodsRequest {
+                counter_id: schedule_demo.into(),
+                page_size: 5,
+                page_token: String::new(),
+            });
+            match client.get_counter_periods(request).await {
+                Ok(response) => {
+                    let periods = response.into_inner().periods;
+                    println!("✅ {} archived periods so far", periods.len());
+                    for period in periods {
+                        let end = period.end_time.map(|t| t.seconds).unwrap_or_default();
+                        println!("  - ended at {}s with value {}", end, period.final_value);
+                    }
+                },
+                Err(err) => println!("❌ GetCounterPeriods failed: {}", err),
+            }
+
+            let request = tonic::Request::new(SetResetScheduleRequest {
+                counter_id: schedule_demo.into(),
+                schedule: String::new(),
+                reset_value: 0,
+            });
+            match client.set_reset_schedule(request).await {
+                Ok(_) => println!("✅ Demo reset schedule removed"),
+                Err(err) => println!("❌ Removing the demo reset schedule failed: {}", err),
+            }
+        },
+        Err(err) => {
+            println!("❌ SetResetSchedule failed: {}", err);
+        }
+    }
+
+    // Test 16: Hand out 25 order IDs locally, leasing them 10 at a time
+    println!("\n=== Testing NextIds RPC ===");
+    let mut order_ids = IdAllocator::new(client.clone(), "order_ids", 10)?;
+    let mut ids = Vec::new();
+    for _ in 0..25 {
+        match order_ids.next_id().await {
+            Ok(id) => ids.push(id),
+            Err(err) => {
+                println!("❌ NextIds failed: {}", err);
+                break;
+            }
+        }
+    }
+    if let (Some(first), Some(last)) = (ids.first(), ids.last()) {
+        println!("✅ Allocated {} IDs from {} to {} with 3 round trips", ids.len(), first, last);
+    }
+
     println!("\n=== Completed All Tests ===");
     println!("The counter value and statistics are persisted in SQLite");
     println!("Each time you run the test, the counters will continue from where they left off");
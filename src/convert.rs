@@ -0,0 +1,440 @@
+//! Conversions between the generated protobuf messages and database types.
+//!
+//! Handlers in `main.rs` validate requests and call `Database`; everything
+//! that maps a database type onto a gRPC message (or back) lives here, along
+//! with `resolve_counter_id`, which maps a requested counter ID onto the one
+//! stored in the database.
+
+use tonic::Status;
+
+use crate::database::{
+    Counter, CounterEvent, CounterEventKind, CounterOperation, CounterOrder, CounterPeriod, CounterStats, Granularity,
+    HistogramBucket, IdBlock, OperationOutcome, OverflowPolicy, QuotaPeriod, RateLimitDecision, SeriesBucket,
+    VersionedValue, MAIN_COUNTER_ID,
+};
+use crate::hello_service::{
+    self, counter_operation::Operation, CompareAndSetResponse, CounterEntry, CounterOperationResult,
+    CounterSortOrder, CreateCounterResponse, DecrementCounterResponse, GetCounterResponse,
+    GetCounterStatsResponse, IncrementCounterResponse, NextIdsResponse, TryAcquireResponse, WatchCounterResponse,
+};
+
+/// Maximum length of a counter ID accepted over gRPC
+pub const MAX_COUNTER_ID_LEN: usize = 128;
+
+/// Resolves the counter ID sent by a client
+///
+/// An empty ID refers to the main counter. Any other ID must be at most
+/// `MAX_COUNTER_ID_LEN` bytes long and consist only of ASCII letters, digits,
+/// `_`, `-`, `.` and `:`.
+///
+/// # Returns
+///
+/// The counter ID to use, or an `INVALID_ARGUMENT` status if it is malformed
+#[allow(clippy::result_large_err)]
+pub fn resolve_counter_id(counter_id: &str) -> Result<&str, Status> {
+    if counter_id.is_empty() {
+        return Ok(MAIN_COUNTER_ID);
+    }
+
+    if counter_id.len() > MAX_COUNTER_ID_LEN {
+        return Err(Status::invalid_argument(format!(
+            "counter_id must be at most {} characters long",
+            MAX_COUNTER_ID_LEN
+        )));
+    }
+
+    let valid_chars = counter_id
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.' | ':'));
+    if !valid_chars {
+        return Err(Status::invalid_argument(
+            "counter_id may only contain ASCII letters, digits, '_', '-', '.' and ':'",
+        ));
+    }
+
+    Ok(counter_id)
+}
+
+/// Converts seconds since the Unix epoch into a protobuf timestamp
+pub fn unix_timestamp(seconds: i64) -> prost_types::Timestamp {
+    prost_types::Timestamp { seconds, nanos: 0 }
+}
+
+/// Converts milliseconds since the Unix epoch into a protobuf timestamp
+pub fn millis_timestamp(millis: i64) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: millis.div_euclid(1000),
+        nanos: (millis.rem_euclid(1000) * 1_000_000) as i32,
+    }
+}
+
+/// Converts a protobuf timestamp into milliseconds since the Unix epoch
+///
+/// Sub-millisecond precision is truncated; timestamps outside the range of
+/// `i64` milliseconds are rejected.
+#[allow(clippy::result_large_err)]
+pub fn timestamp_millis(timestamp: &prost_types::Timestamp) -> Result<i64, Status> {
+    timestamp
+        .seconds
+        .checked_mul(1000)
+        .and_then(|millis| millis.checked_add(i64::from(timestamp.nanos) / 1_000_000))
+        .ok_or_else(|| Status::invalid_argument("timestamp is out of range"))
+}
+
+/// Converts milliseconds into a protobuf duration
+pub fn millis_duration(millis: i64) -> prost_types::Duration {
+    prost_types::Duration {
+        seconds: millis / 1000,
+        nanos: (millis % 1000 * 1_000_000) as i32,
+    }
+}
+
+/// Converts a protobuf duration into milliseconds
+///
+/// Only positive durations made of whole milliseconds are accepted.
+#[allow(clippy::result_large_err)]
+pub fn duration_millis(duration: &prost_types::Duration) -> Result<i64, Status> {
+    if duration.nanos % 1_000_000 != 0 {
+        return Err(Status::invalid_argument("durations must be whole milliseconds"));
+    }
+    let millis = duration
+        .seconds
+        .checked_mul(1000)
+        .and_then(|millis| millis.checked_add(i64::from(duration.nanos / 1_000_000)))
+        .ok_or_else(|| Status::invalid_argument("duration is out of range"))?;
+    if millis <= 0 {
+        return Err(Status::invalid_argument("durations must be positive"));
+    }
+    Ok(millis)
+}
+
+/// Clamps a value to the int32 range, for the deprecated int32 copies of int64 fields
+pub fn clamp_int32(value: i64) -> i32 {
+    value.clamp(i64::from(i32::MIN), i64::from(i32::MAX)) as i32
+}
+
+/// Reads a request field widened to int64: the int64 field unless it is 0,
+/// otherwise its deprecated int32 copy
+pub fn widened(value: i64, value_int32: i32) -> i64 {
+    if value != 0 {
+        value
+    } else {
+        i64::from(value_int32)
+    }
+}
+
+/// Converts a protobuf counter operation into a database operation
+#[allow(clippy::result_large_err)]
+pub fn counter_operation(operation: hello_service::CounterOperation) -> Result<CounterOperation, Status> {
+    let id = resolve_counter_id(&operation.counter_id)?.to_string();
+    match operation.operation {
+        Some(Operation::IncrementBy(amount)) => Ok(CounterOperation::Increment { id, amount }),
+        Some(Operation::SetValue(value)) => Ok(CounterOperation::Set { id, value }),
+        Some(Operation::IncrementByInt32(amount)) => Ok(CounterOperation::Increment { id, amount: amount.into() }),
+        Some(Operation::SetValueInt32(value)) => Ok(CounterOperation::Set { id, value: value.into() }),
+        Some(Operation::Delete(true)) => Ok(CounterOperation::Delete { id }),
+        Some(Operation::Delete(false)) => Err(Status::invalid_argument(format!(
+            "delete operation on counter '{}' must be true",
+            id
+        ))),
+        None => Err(Status::invalid_argument(format!(
+            "operation on counter '{}' has no operation set",
+            id
+        ))),
+    }
+}
+
+/// Builds the ApplyBatch result of one operation from its outcome
+pub fn operation_result(operation: &CounterOperation, outcome: OperationOutcome) -> CounterOperationResult {
+    let written = outcome.written();
+    CounterOperationResult {
+        counter_id: operation.id().to_string(),
+        value: written.map(|r| r.value).unwrap_or_default(),
+        value_int32: written.map(|r| clamp_int32(r.value)).unwrap_or_default(),
+        version: written.map(|r| r.version).unwrap_or_default(),
+        deleted: outcome == OperationOutcome::Deleted,
+    }
+}
+
+/// Converts the `order_by` field of a ListCounters request into a sort order
+#[allow(clippy::result_large_err)]
+pub fn counter_order(order_by: i32) -> Result<CounterOrder, Status> {
+    match CounterSortOrder::try_from(order_by) {
+        Ok(CounterSortOrder::Id) => Ok(CounterOrder::Id),
+        Ok(CounterSortOrder::Value) => Ok(CounterOrder::Value),
+        Err(_) => Err(Status::invalid_argument("unknown order_by value")),
+    }
+}
+
+/// Converts the `policy` field of a SetOverflowPolicy request into a policy
+#[allow(clippy::result_large_err)]
+pub fn overflow_policy(policy: i32) -> Result<OverflowPolicy, Status> {
+    match hello_service::OverflowPolicy::try_from(policy) {
+        Ok(hello_service::OverflowPolicy::Error) => Ok(OverflowPolicy::Error),
+        Ok(hello_service::OverflowPolicy::Saturate) => Ok(OverflowPolicy::Saturate),
+        Ok(hello_service::OverflowPolicy::Wrap) => Ok(OverflowPolicy::Wrap),
+        Err(_) => Err(Status::invalid_argument("unknown overflow policy")),
+    }
+}
+
+/// Converts the `period` field of a SetQuota request into a quota period (`None` if it never resets)
+#[allow(clippy::result_large_err)]
+pub fn quota_period(period: i32) -> Result<Option<QuotaPeriod>, Status> {
+    match hello_service::QuotaPeriod::try_from(period) {
+        Ok(hello_service::QuotaPeriod::None) => Ok(None),
+        Ok(hello_service::QuotaPeriod::Day) => Ok(Some(QuotaPeriod::Day)),
+        Ok(hello_service::QuotaPeriod::Week) => Ok(Some(QuotaPeriod::Week)),
+        Ok(hello_service::QuotaPeriod::Month) => Ok(Some(QuotaPeriod::Month)),
+        Err(_) => Err(Status::invalid_argument("unknown quota period")),
+    }
+}
+
+/// Converts the `granularity` field of a GetCounterSeries request into a granularity
+#[allow(clippy::result_large_err)]
+pub fn granularity(granularity: i32) -> Result<Granularity, Status> {
+    match hello_service::SeriesGranularity::try_from(granularity) {
+        Ok(hello_service::SeriesGranularity::Minute) => Ok(Granularity::Minute),
+        Ok(hello_service::SeriesGranularity::Hour) => Ok(Granularity::Hour),
+        Ok(hello_service::SeriesGranularity::Day) => Ok(Granularity::Day),
+        Err(_) => Err(Status::invalid_argument("unknown granularity")),
+    }
+}
+
+/// Builds a WatchCounter update from a counter's value (`None` if deleted)
+pub fn watch_update(counter_id: &str, value: Option<i64>) -> WatchCounterResponse {
+    WatchCounterResponse {
+        counter_id: counter_id.to_string(),
+        value: value.unwrap_or_default(),
+        value_int32: value.map(clamp_int32).unwrap_or_default(),
+        deleted: value.is_none(),
+    }
+}
+
+impl From<Counter> for CounterEntry {
+    fn from(counter: Counter) -> Self {
+        CounterEntry {
+            counter_id: counter.id,
+            value: counter.value,
+            value_int32: clamp_int32(counter.value),
+        }
+    }
+}
+
+impl From<Counter> for GetCounterResponse {
+    fn from(counter: Counter) -> Self {
+        GetCounterResponse {
+            value: counter.value,
+            value_int32: clamp_int32(counter.value),
+            version: counter.version,
+            expires_at: counter.expires_at.map(millis_timestamp),
+        }
+    }
+}
+
+impl From<CounterStats> for GetCounterStatsResponse {
+    fn from(stats: CounterStats) -> Self {
+        GetCounterStatsResponse {
+            counter_id: stats.id,
+            value: stats.current_value,
+            value_int32: clamp_int32(stats.current_value),
+            total_increments: stats.total_increments,
+            total_increments_int32: clamp_int32(stats.total_increments),
+            average_increment: stats.average_increment,
+            highest_value: stats.highest_value,
+            highest_value_int32: clamp_int32(stats.highest_value),
+            description: stats.description,
+            created_at: Some(unix_timestamp(stats.created_at)),
+            updated_at: Some(unix_timestamp(stats.updated_at)),
+            total_decrements: stats.total_decrements,
+            lowest_value: stats.lowest_value,
+            last_increment_at: stats.last_increment_at.map(millis_timestamp),
+            last_decrement_at: stats.last_decrement_at.map(millis_timestamp),
+            increments_per_minute: stats.increments_per_minute,
+            p50_increment: stats.increment_histogram.percentile(50.0),
+            p90_increment: stats.increment_histogram.percentile(90.0),
+            p99_increment: stats.increment_histogram.percentile(99.0),
+            increment_histogram: stats.increment_histogram.buckets.into_iter().map(Into::into).collect(),
+        }
+    }
+}
+
+impl From<VersionedValue> for IncrementCounterResponse {
+    fn from(applied: VersionedValue) -> Self {
+        IncrementCounterResponse {
+            value: applied.value,
+            value_int32: clamp_int32(applied.value),
+            version: applied.version,
+        }
+    }
+}
+
+impl From<VersionedValue> for DecrementCounterResponse {
+    fn from(applied: VersionedValue) -> Self {
+        DecrementCounterResponse {
+            value: applied.value,
+            version: applied.version,
+        }
+    }
+}
+
+impl From<VersionedValue> for CreateCounterResponse {
+    fn from(created: VersionedValue) -> Self {
+        CreateCounterResponse {
+            value: created.value,
+            version: created.version,
+        }
+    }
+}
+
+impl From<VersionedValue> for CompareAndSetResponse {
+    fn from(applied: VersionedValue) -> Self {
+        CompareAndSetResponse {
+            value: applied.value,
+            value_int32: clamp_int32(applied.value),
+            version: applied.version,
+        }
+    }
+}
+
+impl From<CounterEventKind> for hello_service::CounterEventKind {
+    fn from(kind: CounterEventKind) -> Self {
+        match kind {
+            CounterEventKind::Increment => hello_service::CounterEventKind::Increment,
+            CounterEventKind::Set => hello_service::CounterEventKind::Set,
+            CounterEventKind::Delete => hello_service::CounterEventKind::Delete,
+        }
+    }
+}
+
+impl From<CounterEvent> for hello_service::CounterEvent {
+    fn from(event: CounterEvent) -> Self {
+        hello_service::CounterEvent {
+            event_id: event.id,
+            kind: hello_service::CounterEventKind::from(event.kind) as i32,
+            amount: event.amount,
+            value: event.value,
+            reason: event.reason,
+            occurred_at: Some(millis_timestamp(event.occurred_at)),
+        }
+    }
+}
+
+impl From<SeriesBucket> for hello_service::SeriesBucket {
+    fn from(bucket: SeriesBucket) -> Self {
+        hello_service::SeriesBucket {
+            start_time: Some(millis_timestamp(bucket.bucket_start)),
+            total: bucket.total,
+            increments: bucket.increments,
+        }
+    }
+}
+
+impl From<HistogramBucket> for hello_service::HistogramBucket {
+    fn from(bucket: HistogramBucket) -> Self {
+        hello_service::HistogramBucket {
+            lower_bound: bucket.lower_bound(),
+            upper_bound: bucket.upper_bound(),
+            count: bucket.count,
+        }
+    }
+}
+
+impl From<CounterPeriod> for hello_service::CounterPeriod {
+    fn from(period: CounterPeriod) -> Self {
+        hello_service::CounterPeriod {
+            start_time: Some(millis_timestamp(period.period_start)),
+            end_time: Some(millis_timestamp(period.period_end)),
+            final_value: period.final_value,
+            reset_at: Some(millis_timestamp(period.reset_at)),
+        }
+    }
+}
+
+impl From<IdBlock> for NextIdsResponse {
+    fn from(block: IdBlock) -> Self {
+        NextIdsResponse {
+            first_id: block.first,
+            count: block.count,
+        }
+    }
+}
+
+impl From<RateLimitDecision> for TryAcquireResponse {
+    fn from(decision: RateLimitDecision) -> Self {
+        TryAcquireResponse {
+            allowed: decision.allowed,
+            remaining: decision.remaining,
+            retry_after: Some(millis_duration(decision.retry_after)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_counter_id() {
+        // An empty ID falls back to the main counter
+        assert_eq!(resolve_counter_id("").unwrap(), MAIN_COUNTER_ID);
+
+        // Well-formed IDs are passed through unchanged
+        assert_eq!(resolve_counter_id("orders").unwrap(), "orders");
+        assert_eq!(resolve_counter_id("orders.eu").unwrap(), "orders.eu");
+        assert_eq!(resolve_counter_id("api:calls_2024-05").unwrap(), "api:calls_2024-05");
+
+        // Malformed IDs are rejected with INVALID_ARGUMENT
+        for bad in ["has space", "slash/id", "ünïcode", "semi;colon"] {
+            let err = resolve_counter_id(bad).unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+
+        // IDs longer than the limit are rejected
+        let long_id = "a".repeat(MAX_COUNTER_ID_LEN + 1);
+        let err = resolve_counter_id(&long_id).unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        assert!(resolve_counter_id(&long_id[..MAX_COUNTER_ID_LEN]).is_ok());
+    }
+
+    #[test]
+    fn test_millisecond_timestamps_round_trip() {
+        for millis in [0, 1, 999, 1_700_000_000_123, -1, -1001] {
+            assert_eq!(timestamp_millis(&millis_timestamp(millis)).unwrap(), millis);
+        }
+
+        let before_epoch = millis_timestamp(-1);
+        assert_eq!((before_epoch.seconds, before_epoch.nanos), (-1, 999_000_000));
+
+        let too_late = prost_types::Timestamp { seconds: i64::MAX, nanos: 0 };
+        assert!(timestamp_millis(&too_late).is_err());
+    }
+
+    #[test]
+    fn test_millisecond_durations_round_trip() {
+        for millis in [1, 999, 1000, 86_400_123] {
+            assert_eq!(duration_millis(&millis_duration(millis)).unwrap(), millis);
+        }
+
+        let sub_millisecond = prost_types::Duration { seconds: 1, nanos: 500 };
+        assert!(duration_millis(&sub_millisecond).is_err());
+        assert!(duration_millis(&prost_types::Duration::default()).is_err());
+        assert!(duration_millis(&prost_types::Duration { seconds: -1, nanos: 0 }).is_err());
+    }
+
+    #[test]
+    fn test_int32_fields_stay_readable() {
+        // Responses clamp the deprecated int32 copies instead of truncating them
+        assert_eq!(clamp_int32(-5), -5);
+        assert_eq!(clamp_int32(i64::from(i32::MAX) + 1), i32::MAX);
+        assert_eq!(clamp_int32(i64::MIN), i32::MIN);
+
+        // Requests from int32 clients only set the int32 copy
+        assert_eq!(widened(0, -7), -7);
+        assert_eq!(widened(1 << 40, 3), 1 << 40);
+        let operation = hello_service::CounterOperation {
+            counter_id: "legacy".into(),
+            operation: Some(Operation::IncrementByInt32(2)),
+        };
+        assert_eq!(counter_operation(operation).unwrap(), CounterOperation::Increment { id: "legacy".into(), amount: 2 });
+    }
+}
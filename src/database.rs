@@ -4,23 +4,592 @@
 //! - Connection to SQLite database
 //! - Applying migrations from the migrations directory
 //! - Managing counters (increment, get, set, delete)
+//! - Recording the history of every change made to a counter
+//! - Reading a counter's value as of a point in time from that history
+//! - Rolling increments up into minute, hour and day buckets
+//! - Keeping a histogram of the amounts each counter is incremented by
+//! - Remembering the results of increments sent with an idempotency key
+//! - Expiring counters once their `expires_at` time has passed
+//! - Rate limiting keys with the generic cell rate algorithm (GCRA)
+//! - Enforcing quotas on increments, optionally reset every day, week or month
+//! - Resetting counters on cron-like schedules, archiving each period's final value
+//! - Reserving blocks of unique IDs from named sequences
 
-use anyhow::{anyhow, Result};
+use crate::error::{DatabaseError, Result};
+use crate::schedule::{civil_from_days, CronSchedule};
 use sqlx::{
     migrate::{Migrator, MigrateDatabase}, 
-    sqlite::{SqlitePool, SqlitePoolOptions}, 
-    Row, Sqlite
+    sqlite::{SqliteConnection, SqlitePool, SqlitePoolOptions}, 
+    QueryBuilder, Row, Sqlite, Transaction
 };
-use std::{path::Path, sync::Arc};
+use std::{fmt, path::Path, sync::Arc, time::{Duration, SystemTime}};
+use tokio::sync::broadcast;
 
 /// The ID used for the main application counter
 pub const MAIN_COUNTER_ID: &str = "main_counter";
 
+/// Number of counter changes buffered for each subscriber
+///
+/// Subscribers that fall further behind than this miss the oldest changes
+/// and are told how many they skipped, so writers never wait on them.
+pub const CHANGE_CHANNEL_CAPACITY: usize = 1024;
+
+/// Number of events recorded for a counter between two checkpoints
+///
+/// A point-in-time read replays at most this many events on top of the
+/// closest checkpoint.
+pub const CHECKPOINT_INTERVAL: i64 = 100;
+
+/// Time constant of the exponentially weighted increment rate, in minutes
+///
+/// An increment's weight in the rate halves roughly every 3.5 minutes
+/// (`ln 2` time constants).
+pub const RATE_TIME_CONSTANT_MINUTES: f64 = 5.0;
+
+/// How long the result of an increment is kept for its idempotency key, by default
+///
+/// A request retried after this long is applied again.
+pub const DEFAULT_IDEMPOTENCY_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Returns the current time in milliseconds since the Unix epoch
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as i64)
+        .unwrap_or_default()
+}
+
+/// Decays an increment rate recorded at `since` to what it is at `now`
+///
+/// Both times are in milliseconds since the Unix epoch.
+fn decayed_rate(rate: f64, since: Option<i64>, now: i64) -> f64 {
+    match since {
+        Some(since) => {
+            let minutes = (now - since).max(0) as f64 / 60_000.0;
+            rate * (-minutes / RATE_TIME_CONSTANT_MINUTES).exp()
+        }
+        None => 0.0,
+    }
+}
+
+/// A change made to a counter through `Database`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterChange {
+    /// ID of the counter that changed
+    pub id: String,
+    /// The counter's new value, or `None` if it was deleted
+    pub value: Option<i64>,
+}
+
+/// Sort order used when listing counters
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterOrder {
+    /// Order by counter ID
+    Id,
+    /// Order by counter value, ties broken by ID
+    Value,
+}
+
+/// What happens when an increment would take a counter past the `i64` range
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Reject the increment and leave the counter unchanged
+    #[default]
+    Error,
+    /// Clamp the value to `i64::MIN` or `i64::MAX`
+    Saturate,
+    /// Wrap around using two's complement arithmetic
+    Wrap,
+}
+
+impl OverflowPolicy {
+    /// Returns the name under which the policy is stored in the database
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Saturate => "saturate",
+            Self::Wrap => "wrap",
+        }
+    }
+
+    /// Parses a policy name as stored in the database
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Self::Error),
+            "saturate" => Some(Self::Saturate),
+            "wrap" => Some(Self::Wrap),
+            _ => None,
+        }
+    }
+
+    /// Adds `amount` to `current` according to the policy
+    ///
+    /// # Returns
+    ///
+    /// The new value, or `None` if the addition overflows under `Error`
+    pub fn apply(self, current: i64, amount: i64) -> Option<i64> {
+        match self {
+            Self::Error => current.checked_add(amount),
+            Self::Saturate => Some(current.saturating_add(amount)),
+            Self::Wrap => Some(current.wrapping_add(amount)),
+        }
+    }
+}
+
+/// Optional limits a counter's value must stay within (both inclusive)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, sqlx::FromRow)]
+pub struct CounterBounds {
+    /// Lowest value the counter may take, if any
+    pub min_value: Option<i64>,
+    /// Highest value the counter may take, if any
+    pub max_value: Option<i64>,
+}
+
+impl CounterBounds {
+    /// Returns true unless the lower bound is above the upper bound
+    pub fn is_valid(&self) -> bool {
+        match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) => min <= max,
+            _ => true,
+        }
+    }
+
+    /// Returns true if `value` lies within the bounds
+    pub fn contains(&self, value: i64) -> bool {
+        self.min_value.is_none_or(|min| value >= min) && self.max_value.is_none_or(|max| value <= max)
+    }
+}
+
+/// Optional quota on how far increments may take a counter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, sqlx::FromRow)]
+pub struct CounterQuota {
+    /// Highest value increments may take the counter to, if any
+    #[sqlx(rename = "quota_limit")]
+    pub limit: Option<i64>,
+    /// How often the counter is reset to zero to start a new quota period, if ever
+    #[sqlx(rename = "quota_period")]
+    pub period: Option<QuotaPeriod>,
+}
+
+impl fmt::Display for CounterBounds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.min_value, self.max_value) {
+            (Some(min), Some(max)) => write!(f, "between {} and {}", min, max),
+            (Some(min), None) => write!(f, "at least {}", min),
+            (None, Some(max)) => write!(f, "at most {}", max),
+            (None, None) => write!(f, "unbounded"),
+        }
+    }
+}
+
+/// Builds the error returned when a change would break a counter's bounds
+fn bounds_violation(id: &str, attempted: i64, bounds: CounterBounds) -> DatabaseError {
+    DatabaseError::ConstraintViolation {
+        message: format!(
+            "counter '{}' must stay {}, but the change would make it {}",
+            id, bounds, attempted
+        ),
+    }
+}
+
+/// A counter's value together with its version
+///
+/// The version starts at 1 when the counter is created and is bumped by
+/// every write, so it identifies the exact state a client has read. A counter
+/// recreated after a delete carries on from the deleted counter's version,
+/// so versions never repeat for the same ID.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VersionedValue {
+    /// The counter's value
+    pub value: i64,
+    /// The counter's version
+    pub version: i64,
+}
+
+/// Columns selected from `counters` to build a `Counter`
+///
+/// Timestamps are stored as SQLite `CURRENT_TIMESTAMP` text and converted
+/// to seconds since the Unix epoch here.
+const COUNTER_COLUMNS: &str = "id, value, version, description, min_value, max_value,
+    CAST(strftime('%s', created_at) AS INTEGER) AS created_at,
+    CAST(strftime('%s', updated_at) AS INTEGER) AS updated_at, expires_at, quota_limit, quota_period";
+
+/// Condition matching counters that have not expired at the time bound to `?`
+const NOT_EXPIRED: &str = "(expires_at IS NULL OR expires_at > ?)";
+
+/// A counter as stored in the database
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct Counter {
+    /// ID of the counter
+    pub id: String,
+    /// Current value of the counter
+    pub value: i64,
+    /// Version of the counter, bumped by every write
+    pub version: i64,
+    /// Optional human readable description
+    pub description: Option<String>,
+    /// Limits the counter's value must stay within
+    #[sqlx(flatten)]
+    pub bounds: CounterBounds,
+    /// Creation time in seconds since the Unix epoch
+    pub created_at: i64,
+    /// Last update time in seconds since the Unix epoch
+    pub updated_at: i64,
+    /// Expiry time in milliseconds since the Unix epoch, if the counter expires
+    pub expires_at: Option<i64>,
+    /// Quota enforced on increments of the counter
+    #[sqlx(flatten)]
+    pub quota: CounterQuota,
+}
+
+impl Counter {
+    /// Returns the counter's value together with its version
+    pub fn versioned(&self) -> VersionedValue {
+        VersionedValue {
+            value: self.value,
+            version: self.version,
+        }
+    }
+}
+
+/// A single write applied as part of `Database::apply_batch`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CounterOperation {
+    /// Increment a counter, creating it if needed
+    Increment { id: String, amount: i64 },
+    /// Set a counter to a value, creating it if needed
+    Set { id: String, value: i64 },
+    /// Delete a counter if it exists
+    Delete { id: String },
+}
+
+impl CounterOperation {
+    /// Returns the ID of the counter the operation applies to
+    pub fn id(&self) -> &str {
+        match self {
+            Self::Increment { id, .. } | Self::Set { id, .. } | Self::Delete { id } => id,
+        }
+    }
+}
+
+/// Outcome of one operation applied by `Database::apply_batch`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationOutcome {
+    /// The counter was incremented or set, leaving it with this value and version
+    Written(VersionedValue),
+    /// The counter was deleted
+    Deleted,
+    /// The counter to delete did not exist (or had already expired)
+    NotFound,
+}
+
+impl OperationOutcome {
+    /// Returns the counter's value and version after a write, `None` otherwise
+    pub fn written(self) -> Option<VersionedValue> {
+        match self {
+            Self::Written(value) => Some(value),
+            Self::Deleted | Self::NotFound => None,
+        }
+    }
+}
+
+/// Position of the last counter returned by `list_counters_page`
+///
+/// Passing it back resumes the listing right after that counter.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CounterCursor {
+    /// ID of the last counter returned
+    pub id: String,
+    /// Value of the last counter returned (only used when ordering by value)
+    pub value: i64,
+}
+
+impl From<&Counter> for CounterCursor {
+    fn from(counter: &Counter) -> Self {
+        CounterCursor {
+            id: counter.id.clone(),
+            value: counter.value,
+        }
+    }
+}
+
+/// Kind of change recorded in a counter's history
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum CounterEventKind {
+    /// The counter was incremented (or decremented) by an amount
+    Increment,
+    /// The counter was created or set to a value
+    Set,
+    /// The counter was deleted
+    Delete,
+}
+
+/// A change recorded in the `counter_events` table
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct CounterEvent {
+    /// ID of the event, increasing in the order events were recorded
+    pub id: i64,
+    /// ID of the counter that changed
+    pub counter_id: String,
+    /// What kind of change this was
+    pub kind: CounterEventKind,
+    /// Amount added by an increment, `None` for other kinds
+    pub amount: Option<i64>,
+    /// The counter's value after the change, `None` for deletes
+    pub value: Option<i64>,
+    /// Optional reason given for the change
+    pub reason: Option<String>,
+    /// Time of the change in milliseconds since the Unix epoch
+    pub occurred_at: i64,
+}
+
+/// Size of the time buckets increments are rolled up into
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum Granularity {
+    /// One bucket per minute
+    Minute,
+    /// One bucket per hour
+    Hour,
+    /// One bucket per day (UTC)
+    Day,
+}
+
+impl Granularity {
+    /// Every granularity, each of which is kept up to date on increment
+    pub const ALL: [Granularity; 3] = [Granularity::Minute, Granularity::Hour, Granularity::Day];
+
+    /// Returns the length of a bucket in milliseconds
+    pub fn millis(self) -> i64 {
+        match self {
+            Self::Minute => 60_000,
+            Self::Hour => 3_600_000,
+            Self::Day => 86_400_000,
+        }
+    }
+
+    /// Returns the start of the bucket containing `millis`
+    pub fn bucket_start(self, millis: i64) -> i64 {
+        millis - millis.rem_euclid(self.millis())
+    }
+}
+
+/// How often a quota counter is reset to start a new quota period (UTC)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type)]
+#[sqlx(rename_all = "lowercase")]
+pub enum QuotaPeriod {
+    /// A new period starts every day at midnight
+    Day,
+    /// A new period starts every Monday at midnight
+    Week,
+    /// A new period starts on the first day of every month
+    Month,
+}
+
+impl QuotaPeriod {
+    /// Returns the start of the period containing `millis`
+    ///
+    /// Both times are in milliseconds since the Unix epoch.
+    pub fn period_start(self, millis: i64) -> i64 {
+        const DAY_MILLIS: i64 = 86_400_000;
+        let day = millis.div_euclid(DAY_MILLIS);
+        let first_day = match self {
+            Self::Day => day,
+            // 1970-01-01 was a Thursday, so Mondays are 4 days past a multiple of 7
+            Self::Week => day - (day - 4).rem_euclid(7),
+            Self::Month => day - (i64::from(civil_from_days(day).2) - 1),
+        };
+        first_day * DAY_MILLIS
+    }
+}
+
+/// Increments applied to a counter within one time bucket
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::FromRow)]
+pub struct SeriesBucket {
+    /// Start of the bucket in milliseconds since the Unix epoch
+    pub bucket_start: i64,
+    /// Sum of the amounts the counter was incremented by
+    pub total: i64,
+    /// Number of increments applied
+    pub increments: i64,
+}
+
+/// One bucket of an `IncrementHistogram`
+///
+/// Buckets are powers of two: bucket `b` counts amounts from `2^(b-1)` to
+/// `2^b - 1`, for `b` from 1 to 63.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::FromRow)]
+pub struct HistogramBucket {
+    /// Index of the bucket, which is the bit length of the amounts it counts
+    pub bucket: u32,
+    /// Number of increments whose amount fell into the bucket
+    pub count: i64,
+}
+
+impl HistogramBucket {
+    /// Returns the bucket an increment amount falls into, `None` unless it is positive
+    pub fn index_of(amount: i64) -> Option<u32> {
+        (amount > 0).then(|| i64::BITS - amount.leading_zeros())
+    }
+
+    /// Returns the smallest amount counted in the bucket
+    pub fn lower_bound(&self) -> i64 {
+        1 << (self.bucket - 1)
+    }
+
+    /// Returns the largest amount counted in the bucket
+    pub fn upper_bound(&self) -> i64 {
+        self.lower_bound() - 1 + self.lower_bound()
+    }
+}
+
+/// Histogram of the positive amounts a counter has been incremented by
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IncrementHistogram {
+    /// Non-empty buckets in ascending order
+    pub buckets: Vec<HistogramBucket>,
+}
+
+impl IncrementHistogram {
+    /// Returns the total number of increments in the histogram
+    pub fn total(&self) -> i64 {
+        self.buckets.iter().map(|b| b.count).sum()
+    }
+
+    /// Estimates the amount below which `percentile` percent of increments fall
+    ///
+    /// Finds the bucket holding the increment of that rank and interpolates
+    /// linearly between the bucket's bounds, so the estimate is exact for
+    /// amounts of 1 and within a factor of two otherwise.
+    ///
+    /// # Returns
+    ///
+    /// The estimated amount, or `None` if the histogram is empty
+    pub fn percentile(&self, percentile: f64) -> Option<i64> {
+        let total = self.total();
+        if total == 0 {
+            return None;
+        }
+
+        let rank = ((percentile / 100.0 * total as f64).ceil() as i64).clamp(1, total);
+        let mut seen = 0;
+        for bucket in &self.buckets {
+            if seen + bucket.count >= rank {
+                let fraction = (rank - seen) as f64 / bucket.count as f64;
+                let width = (bucket.upper_bound() - bucket.lower_bound()) as f64;
+                return Some(bucket.lower_bound() + (width * fraction).round() as i64);
+            }
+            seen += bucket.count;
+        }
+        None
+    }
+}
+
+/// Statistics tracked for a counter, as read from the `counter_stats` view
+#[derive(Debug, Clone, PartialEq, sqlx::FromRow)]
+pub struct CounterStats {
+    /// ID of the counter
+    pub id: String,
+    /// Current value of the counter
+    pub current_value: i64,
+    /// Number of times the counter has been incremented
+    pub total_increments: i64,
+    /// Average amount the counter has been incremented by
+    pub average_increment: f64,
+    /// Highest value the counter has reached
+    pub highest_value: i64,
+    /// Number of times the counter's value has gone down
+    pub total_decrements: i64,
+    /// Lowest value the counter has reached
+    pub lowest_value: i64,
+    /// Last time the value went up, in milliseconds since the Unix epoch
+    pub last_increment_at: Option<i64>,
+    /// Last time the value went down, in milliseconds since the Unix epoch
+    pub last_decrement_at: Option<i64>,
+    /// Exponentially weighted number of increments per minute, as of
+    /// `rate_updated_at`; `get_counter_stats` decays it to the time of the read
+    #[sqlx(rename = "increment_rate")]
+    pub increments_per_minute: f64,
+    /// Time of the last increment counted in the rate, in milliseconds since
+    /// the Unix epoch
+    pub rate_updated_at: Option<i64>,
+    /// Histogram of the amounts the counter has been incremented by, read
+    /// from `counter_histograms`
+    #[sqlx(skip)]
+    pub increment_histogram: IncrementHistogram,
+    /// Optional human readable description
+    pub description: Option<String>,
+    /// Creation time in seconds since the Unix epoch
+    pub created_at: i64,
+    /// Last update time in seconds since the Unix epoch
+    pub updated_at: i64,
+}
+
+/// Outcome of `Database::try_acquire`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimitDecision {
+    /// Whether the permits were granted
+    pub allowed: bool,
+    /// Number of permits that could still be acquired right away
+    pub remaining: i64,
+    /// Milliseconds to wait before the same request would be allowed (0 if allowed)
+    pub retry_after: i64,
+}
+
+/// A block of consecutive IDs reserved from a sequence by `Database::next_ids`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdBlock {
+    /// First ID of the block
+    pub first: i64,
+    /// Number of IDs in the block
+    pub count: i64,
+}
+
+impl IdBlock {
+    /// Returns the IDs of the block
+    pub fn ids(&self) -> std::ops::Range<i64> {
+        self.first..self.first + self.count
+    }
+}
+
+/// A counter's reset schedule, stored in the `counter_reset_schedules` table
+#[derive(Debug, Clone, PartialEq, Eq, sqlx::FromRow)]
+pub struct ResetSchedule {
+    /// ID of the counter the schedule resets
+    pub counter_id: String,
+    /// The cron-like schedule, as given when it was set
+    pub schedule: String,
+    /// Value the counter is reset to
+    pub reset_value: i64,
+    /// Start of the current period in milliseconds since the Unix epoch
+    pub period_start: i64,
+    /// Time of the next reset in milliseconds since the Unix epoch
+    pub next_reset_at: i64,
+}
+
+/// A period of a counter ended by a scheduled reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::FromRow)]
+pub struct CounterPeriod {
+    /// Start of the period in milliseconds since the Unix epoch
+    pub period_start: i64,
+    /// Scheduled time of the reset that ended the period
+    pub period_end: i64,
+    /// Value of the counter when the period ended
+    pub final_value: i64,
+    /// Time the reset was applied, later than `period_end` if it was missed
+    pub reset_at: i64,
+}
+
 /// Database handler for SQLite operations
 #[derive(Debug, Clone)]
 pub struct Database {
     /// Connection pool for SQLite
     pool: Arc<SqlitePool>,
+    /// Publishes every counter change to subscribers
+    changes: broadcast::Sender<CounterChange>,
+    /// How long the result of an idempotent increment is remembered
+    idempotency_retention: Duration,
 }
 
 impl Database {
@@ -50,8 +619,11 @@ impl Database {
             .await?;
             
         // Create the database instance
+        let (changes, _) = broadcast::channel(CHANGE_CHANNEL_CAPACITY);
         let db = Self {
             pool: Arc::new(pool),
+            changes,
+            idempotency_retention: DEFAULT_IDEMPOTENCY_RETENTION,
         };
 
         // Apply migrations and initialize
@@ -61,6 +633,14 @@ impl Database {
         Ok(db)
     }
 
+    /// Sets how long the results of idempotent increments are remembered
+    ///
+    /// Defaults to `DEFAULT_IDEMPOTENCY_RETENTION`.
+    pub fn with_idempotency_retention(mut self, retention: Duration) -> Self {
+        self.idempotency_retention = retention;
+        self
+    }
+
     /// Applies all pending migrations from the migrations directory
     ///
     /// This method will find the migrations directory and apply all
@@ -71,8 +651,8 @@ impl Database {
         let migrations_path = Path::new("migrations");
         
         if !migrations_path.exists() {
-            return Err(anyhow!("Migrations directory not found at: {}", 
-                migrations_path.display()));
+            return Err(DatabaseError::internal(format!("Migrations directory not found at: {}", 
+                migrations_path.display())));
         }
 
         // Load and run migrations from the migrations directory
@@ -86,77 +666,206 @@ impl Database {
     }
     
     /// Ensures the main counter exists in the database
+    ///
+    /// Creating it is recorded in its history like any other creation, so it
+    /// can be read as of any time after this.
     async fn ensure_main_counter(&self) -> Result<()> {
+        let mut tx = self.begin_write().await?;
+
         // Check if the main counter exists
         let exists = sqlx::query("SELECT 1 FROM counters WHERE id = ?")
             .bind(MAIN_COUNTER_ID)
-            .fetch_optional(&*self.pool)
+            .fetch_optional(&mut *tx)
             .await?
             .is_some();
             
         // Create it if it doesn't exist
         if !exists {
             println!("Creating main counter with ID: {}", MAIN_COUNTER_ID);
-            sqlx::query("INSERT INTO counters (id, value, description) VALUES (?, 0, ?)")
+            let version = Self::initial_version_in(&mut tx, MAIN_COUNTER_ID).await?;
+            sqlx::query("INSERT INTO counters (id, value, description, version) VALUES (?, 0, ?, ?)")
                 .bind(MAIN_COUNTER_ID)
                 .bind("Main application counter")
-                .execute(&*self.pool)
+                .bind(version)
+                .execute(&mut *tx)
                 .await?;
+            Self::record_event_in(&mut tx, MAIN_COUNTER_ID, CounterEventKind::Set, None, Some(0), None).await?;
         }
+        tx.commit().await?;
         
         Ok(())
     }
 
     /// Gets the value of a counter by ID
     ///
-    /// If the counter doesn't exist, it will be created with a value of 0.
-    ///
     /// # Arguments
     ///
     /// * `id` - The ID of the counter to retrieve
     ///
     /// # Returns
     ///
-    /// The current value of the counter
-    pub async fn get_counter(&self, id: &str) -> Result<i32> {
-        let row = sqlx::query("SELECT value FROM counters WHERE id = ?")
+    /// The counter, or `DatabaseError::NotFound` if it doesn't exist or has expired
+    pub async fn get_counter(&self, id: &str) -> Result<Counter> {
+        let query = format!("SELECT {} FROM counters WHERE id = ? AND {}", COUNTER_COLUMNS, NOT_EXPIRED);
+        sqlx::query_as::<_, Counter>(&query)
             .bind(id)
+            .bind(now_millis())
             .fetch_optional(&*self.pool)
+            .await?
+            .ok_or_else(|| DatabaseError::NotFound { id: id.to_string() })
+    }
+
+    /// Gets the value a counter had at a point in time
+    ///
+    /// The value is rebuilt from the `counter_events` log, starting at the
+    /// closest checkpoint taken at or before `as_of` and replaying the events
+    /// recorded after it. History only goes back to the first event recorded
+    /// for the counter.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter to read
+    /// * `as_of` - The point in time, in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The counter's value at that time, or `DatabaseError::NotFound` if the
+    /// counter did not exist then or had already expired
+    pub async fn get_counter_as_of(&self, id: &str, as_of: i64) -> Result<i64> {
+        let mut conn = self.pool.acquire().await?;
+
+        // A counter that has expired but not been deleted yet has no delete event to replay
+        let expires_at: Option<Option<i64>> = sqlx::query_scalar("SELECT expires_at FROM counters WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *conn)
             .await?;
+        if expires_at.flatten().is_some_and(|expires_at| as_of >= expires_at) {
+            return Err(DatabaseError::NotFound { id: id.to_string() });
+        }
 
-        match row {
-            Some(row) => {
-                let value: i32 = row.try_get("value")?;
-                Ok(value)
-            }
-            None => {
-                // If counter doesn't exist, create it with value 0
-                self.set_counter(id, 0).await?;
-                Ok(0)
-            }
+        let checkpoint = sqlx::query(
+            "SELECT event_id, value FROM counter_checkpoints
+             WHERE counter_id = ? AND occurred_at <= ?
+             ORDER BY event_id DESC
+             LIMIT 1"
+        )
+        .bind(id)
+        .bind(as_of)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let (after, mut value) = match checkpoint {
+            Some(row) => (row.try_get::<i64, _>("event_id")?, row.try_get::<Option<i64>, _>("value")?),
+            None => (0, None),
+        };
+
+        let events = sqlx::query_as::<_, (CounterEventKind, Option<i64>)>(
+            "SELECT kind, value FROM counter_events
+             WHERE counter_id = ? AND id > ? AND occurred_at <= ?
+             ORDER BY id"
+        )
+        .bind(id)
+        .bind(after)
+        .bind(as_of)
+        .fetch_all(&mut *conn)
+        .await?;
+
+        // Events carry the value they left the counter at, which also covers
+        // increments clamped or wrapped by the overflow policy
+        for (kind, event_value) in events {
+            value = match kind {
+                CounterEventKind::Increment | CounterEventKind::Set => event_value,
+                CounterEventKind::Delete => None,
+            };
+        }
+
+        value.ok_or_else(|| DatabaseError::NotFound { id: id.to_string() })
+    }
+
+    /// Gets the value of a counter by ID, creating it with a value of 0 if
+    /// it doesn't exist
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter to retrieve
+    ///
+    /// # Returns
+    ///
+    /// The counter, either existing or newly created
+    pub async fn get_or_create_counter(&self, id: &str) -> Result<Counter> {
+        match self.create_counter(id, 0, None, CounterBounds::default(), None).await {
+            Ok(_) | Err(DatabaseError::AlreadyExists { .. }) => self.get_counter(id).await,
+            Err(e) => Err(e),
         }
     }
 
     /// Sets a counter to a specific value
     ///
+    /// Existing counters are updated in place, so their description,
+    /// creation time and statistics are preserved.
+    ///
     /// # Arguments
     ///
     /// * `id` - The ID of the counter to set
     /// * `value` - The new value for the counter
-    pub async fn set_counter(&self, id: &str, value: i32) -> Result<()> {
-        sqlx::query("INSERT OR REPLACE INTO counters (id, value) VALUES (?, ?)")
+    ///
+    /// # Returns
+    ///
+    /// The new value and version of the counter
+    pub async fn set_counter(&self, id: &str, value: i64) -> Result<VersionedValue> {
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let new_value = Self::set_in(&mut tx, id, value).await?;
+        tx.commit().await?;
+
+        self.publish_expiry(id, expired);
+        self.publish(id, Some(new_value.value));
+        Ok(new_value)
+    }
+
+    /// Sets a counter on the given connection, see `set_counter`
+    ///
+    /// Must run inside a write transaction, as the counter's bounds are
+    /// checked before writing. An expired counter must have been deleted
+    /// first, see `expire_in`.
+    async fn set_in(conn: &mut SqliteConnection, id: &str, value: i64) -> Result<VersionedValue> {
+        Self::check_bounds_in(conn, id, value).await?;
+
+        let previous: Option<i64> = sqlx::query_scalar("SELECT value FROM counters WHERE id = ?")
             .bind(id)
-            .bind(value)
-            .execute(&*self.pool)
+            .fetch_optional(&mut *conn)
             .await?;
+        let version = Self::initial_version_in(conn, id).await?;
+        let row = sqlx::query(
+            "INSERT INTO counters (id, value, version) VALUES (?, ?, ?)
+             ON CONFLICT(id) DO UPDATE SET value = excluded.value, version = version + 1
+             RETURNING value, version"
+        )
+        .bind(id)
+        .bind(value)
+        .bind(version)
+        .fetch_one(&mut *conn)
+        .await?;
 
-        Ok(())
+        let new_value = VersionedValue {
+            value: row.try_get("value")?,
+            version: row.try_get("version")?,
+        };
+        let occurred_at =
+            Self::record_event_in(conn, id, CounterEventKind::Set, None, Some(new_value.value), None).await?;
+        if previous.is_some_and(|previous| new_value.value > previous) {
+            Self::count_increase_in(conn, id, occurred_at).await?;
+        }
+        Ok(new_value)
     }
 
     /// Increments a counter by the specified amount and returns the new value
     ///
-    /// This operation is atomic and uses a transaction to ensure consistency
-    /// even with concurrent access.
+    /// This operation is atomic: it runs in a write transaction, so concurrent
+    /// increments never lose updates. Existing counters are updated in place,
+    /// which keeps their metadata and lets the statistics triggers fire. A
+    /// counter that doesn't exist yet is created with the increment as its
+    /// value.
     ///
     /// # Arguments
     ///
@@ -165,154 +874,2505 @@ impl Database {
     ///
     /// # Returns
     ///
-    /// The new value of the counter after incrementing
-    pub async fn increment_counter(&self, id: &str, amount: i32) -> Result<i32> {
-        // Start a transaction to ensure atomicity
-        let mut tx = self.pool.begin().await?;
-
-        // Get the current counter value or use 0 if it doesn't exist
-        let current_value = sqlx::query("SELECT value FROM counters WHERE id = ?")
-            .bind(id)
-            .fetch_optional(&mut *tx)
-            .await?;
-
-        let current_value = match current_value {
-            Some(row) => row.try_get::<i32, _>("value")?,
-            None => 0,
-        };
-
-        // Calculate the new value
-        let new_value = current_value + amount;
-
-        // Update the counter with the new value
-        // The highest_value and average_increment will be updated by the trigger
-        sqlx::query("INSERT OR REPLACE INTO counters (id, value) VALUES (?, ?)")
-            .bind(id)
-            .bind(new_value)
-            .execute(&mut *tx)
-            .await?;
-
-        // Commit the transaction
-        tx.commit().await?;
-
-        Ok(new_value)
+    /// The new value and version of the counter after incrementing. If the
+    /// new value would not fit in an `i64`, the counter's `OverflowPolicy`
+    /// decides the outcome; under `OverflowPolicy::Error` a
+    /// `DatabaseError::OutOfRange` is returned.
+    pub async fn increment_counter(&self, id: &str, amount: i64) -> Result<VersionedValue> {
+        self.increment_counter_with_reason(id, amount, None, None).await
     }
 
-    /// Lists all counters in the database along with their values
+    /// Increments a counter like `increment_counter`, recording a reason for
+    /// the change in the counter's history and optionally moving its expiry
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter to increment
+    /// * `amount` - The amount to increment by
+    /// * `reason` - Optional reason stored with the history event
+    /// * `expires_at` - New expiry time in milliseconds since the Unix epoch;
+    ///   `None` keeps the counter's current expiry
     ///
     /// # Returns
     ///
-    /// A vector of (counter_id, value) pairs
-    pub async fn list_counters(&self) -> Result<Vec<(String, i32)>> {
-        let rows = sqlx::query("SELECT id, value FROM counters ORDER BY id")
-            .fetch_all(&*self.pool)
-            .await?;
-
-        let mut counters = Vec::with_capacity(rows.len());
-        for row in rows {
-            let id: String = row.try_get("id")?;
-            let value: i32 = row.try_get("value")?;
-            counters.push((id, value));
-        }
+    /// The new value and version of the counter after incrementing
+    pub async fn increment_counter_with_reason(
+        &self,
+        id: &str,
+        amount: i64,
+        reason: Option<&str>,
+        expires_at: Option<i64>,
+    ) -> Result<VersionedValue> {
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let new_value = Self::increment_in(&mut tx, id, amount, reason, expires_at).await?;
+        tx.commit().await?;
 
-        Ok(counters)
+        self.publish_expiry(id, expired);
+        self.publish(id, Some(new_value.value));
+        Ok(new_value)
     }
 
-    /// Gets detailed statistics for a counter
+    /// Increments a counter at most once per idempotency key
+    ///
+    /// The first request with a key increments the counter and stores the
+    /// result under the key. Requests repeating the key within the retention
+    /// window get that result back without incrementing the counter again.
     ///
     /// # Arguments
     ///
-    /// * `id` - The ID of the counter to get statistics for
+    /// * `id` - The ID of the counter to increment
+    /// * `amount` - The amount to increment by
+    /// * `reason` - Optional reason stored with the history event
+    /// * `expires_at` - New expiry time of the counter, see `increment_counter_with_reason`
+    /// * `key` - Idempotency key chosen by the caller
     ///
     /// # Returns
     ///
-    /// A tuple containing (current_value, total_increments, average_increment, highest_value)
-    pub async fn get_counter_stats(&self, id: &str) -> Result<Option<(i32, i32, f64, i32)>> {
-        let row = sqlx::query(
-            "SELECT value, total_increments, average_increment, highest_value 
-             FROM counters WHERE id = ?"
+    /// The value and version of the counter right after the key's increment,
+    /// or a `ConstraintViolation` if the key was used for a different increment
+    pub async fn increment_counter_idempotent(
+        &self,
+        id: &str,
+        amount: i64,
+        reason: Option<&str>,
+        expires_at: Option<i64>,
+        key: &str,
+    ) -> Result<VersionedValue> {
+        let now = now_millis();
+        let mut tx = self.begin_write().await?;
+
+        // Expired keys are left to the reaper and ignored here, so they can't be replayed
+        let previous: Option<(String, i64, i64, i64)> = sqlx::query_as(
+            "SELECT counter_id, amount, value, version FROM idempotency_keys WHERE key = ? AND created_at >= ?",
         )
-        .bind(id)
-        .fetch_optional(&*self.pool)
+        .bind(key)
+        .bind(self.idempotency_cutoff(now))
+        .fetch_optional(&mut *tx)
         .await?;
 
-        match row {
-            Some(row) => {
-                let value: i32 = row.try_get("value")?;
-                let total_increments: i32 = row.try_get("total_increments")?;
-                let average_increment: f64 = row.try_get("average_increment")?;
-                let highest_value: i32 = row.try_get("highest_value")?;
-                
-                Ok(Some((value, total_increments, average_increment, highest_value)))
+        if let Some((counter_id, previous_amount, value, version)) = previous {
+            if counter_id != id || previous_amount != amount {
+                return Err(DatabaseError::ConstraintViolation {
+                    message: format!(
+                        "idempotency key '{}' was already used to increment counter '{}' by {}",
+                        key, counter_id, previous_amount
+                    ),
+                });
             }
-            None => Ok(None),
+            return Ok(VersionedValue { value, version });
         }
+
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let new_value = Self::increment_in(&mut tx, id, amount, reason, expires_at).await?;
+        sqlx::query(
+            "INSERT INTO idempotency_keys (key, counter_id, amount, value, version, created_at)
+             VALUES (?, ?, ?, ?, ?, ?)
+             ON CONFLICT(key) DO UPDATE SET
+                 counter_id = excluded.counter_id,
+                 amount = excluded.amount,
+                 value = excluded.value,
+                 version = excluded.version,
+                 created_at = excluded.created_at",
+        )
+        .bind(key)
+        .bind(id)
+        .bind(amount)
+        .bind(new_value.value)
+        .bind(new_value.version)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        self.publish_expiry(id, expired);
+        self.publish(id, Some(new_value.value));
+        Ok(new_value)
     }
 
-    /// Deletes a counter by ID
+    /// Deletes the idempotency keys whose retention window has passed by `now`
+    ///
+    /// Increments already ignore expired keys; this keeps keys that are never
+    /// repeated from piling up.
     ///
     /// # Arguments
     ///
-    /// * `id` - The ID of the counter to delete
+    /// * `now` - The current time in milliseconds since the Unix epoch
     ///
     /// # Returns
     ///
-    /// true if a counter was deleted, false if no counter with that ID existed
-    pub async fn delete_counter(&self, id: &str) -> Result<bool> {
-        let result = sqlx::query("DELETE FROM counters WHERE id = ?")
-            .bind(id)
+    /// The number of keys deleted
+    pub async fn purge_expired_idempotency_keys(&self, now: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM idempotency_keys WHERE created_at < ?")
+            .bind(self.idempotency_cutoff(now))
             .execute(&*self.pool)
             .await?;
 
-        Ok(result.rows_affected() > 0)
+        Ok(result.rows_affected())
     }
-    
-    /// Returns a reference to the underlying connection pool
-    pub fn pool(&self) -> &SqlitePool {
-        &self.pool
+
+    /// Creation time before which idempotency keys have expired by `now`
+    fn idempotency_cutoff(&self, now: i64) -> i64 {
+        let retention = i64::try_from(self.idempotency_retention.as_millis()).unwrap_or(i64::MAX);
+        now.saturating_sub(retention)
+    }
+
+    /// Applies a batch of increments in a single transaction
+    ///
+    /// Either every increment is applied or, if any of them fails, none are.
+    ///
+    /// # Arguments
+    ///
+    /// * `increments` - (counter_id, amount) pairs, applied in order
+    ///
+    /// # Returns
+    ///
+    /// The value and version of each counter after its increment, in the
+    /// same order
+    pub async fn increment_counters(&self, increments: &[(String, i64)]) -> Result<Vec<VersionedValue>> {
+        let mut tx = self.begin_write().await?;
+
+        let mut values = Vec::with_capacity(increments.len());
+        let mut expired = Vec::with_capacity(increments.len());
+        for (id, amount) in increments {
+            expired.push(Self::expire_in(&mut tx, id).await?);
+            values.push(Self::increment_in(&mut tx, id, *amount, None, None).await?);
+        }
+
+        tx.commit().await?;
+
+        for (((id, _), value), expired) in increments.iter().zip(&values).zip(expired) {
+            self.publish_expiry(id, expired);
+            self.publish(id, Some(value.value));
+        }
+        Ok(values)
+    }
+
+    /// Increments a counter on the given connection, see `increment_counter_with_reason`
+    ///
+    /// Must run inside a write transaction, as the new value is computed
+    /// from the value read beforehand. An expired counter must have been
+    /// deleted first, see `expire_in`.
+    async fn increment_in(
+        conn: &mut SqliteConnection,
+        id: &str,
+        amount: i64,
+        reason: Option<&str>,
+        expires_at: Option<i64>,
+    ) -> Result<VersionedValue> {
+        // A quota counter starts from zero in each new quota period
+        Self::roll_quota_period_in(conn, id, now_millis()).await?;
+
+        let current = sqlx::query(
+            "SELECT value, overflow_policy, min_value, max_value, quota_limit FROM counters WHERE id = ?"
+        )
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let previous: i64 = match &current {
+            Some(current) => current.try_get("value")?,
+            None => 0,
+        };
+
+        let row = match current {
+            Some(current) => {
+                let value: i64 = current.try_get("value")?;
+                let policy: String = current.try_get("overflow_policy")?;
+                let policy = OverflowPolicy::parse(&policy).ok_or_else(|| {
+                    DatabaseError::internal(format!("Unknown overflow policy '{}' on counter '{}'", policy, id))
+                })?;
+
+                // The quota caps what the counter was asked to reach, before the
+                // overflow policy saturates or wraps it back under the limit
+                let quota_limit: Option<i64> = current.try_get("quota_limit")?;
+                let over_quota = |limit| value.checked_add(amount).is_none_or(|sum| sum > limit);
+                if let Some(limit) = quota_limit.filter(|&limit| amount > 0 && over_quota(limit)) {
+                    return Err(DatabaseError::QuotaExceeded { id: id.to_string(), limit, value, amount });
+                }
+
+                let new_value = policy.apply(value, amount).ok_or_else(|| DatabaseError::OutOfRange {
+                    id: id.to_string(),
+                    amount,
+                })?;
+
+                let bounds = CounterBounds {
+                    min_value: current.try_get("min_value")?,
+                    max_value: current.try_get("max_value")?,
+                };
+                if !bounds.contains(new_value) {
+                    return Err(bounds_violation(id, new_value, bounds));
+                }
+
+                sqlx::query(
+                    "UPDATE counters SET value = ?, version = version + 1, expires_at = COALESCE(?, expires_at)
+                     WHERE id = ?
+                     RETURNING value, version"
+                )
+                .bind(new_value)
+                .bind(expires_at)
+                .bind(id)
+                .fetch_one(&mut *conn)
+                .await?
+            }
+            None => {
+                // A newly created counter records its first increment directly,
+                // as the statistics triggers only fire on updates
+                let version = Self::initial_version_in(conn, id).await?;
+                sqlx::query(
+                    "INSERT INTO counters (
+                         id, value, total_increments, average_increment, highest_value,
+                         total_decrements, lowest_value, last_increment_at, last_decrement_at, expires_at, version
+                     )
+                     VALUES (
+                         ?1, ?2, ?2 > 0, MAX(?2, 0), MAX(?2, 0), ?2 < 0, MIN(?2, 0),
+                         CASE WHEN ?2 > 0 THEN CAST(unixepoch('subsec') * 1000 AS INTEGER) END,
+                         CASE WHEN ?2 < 0 THEN CAST(unixepoch('subsec') * 1000 AS INTEGER) END,
+                         ?3, ?4
+                     )
+                     RETURNING value, version"
+                )
+                .bind(id)
+                .bind(amount)
+                .bind(expires_at)
+                .bind(version)
+                .fetch_one(&mut *conn)
+                .await?
+            }
+        };
+
+        let new_value = VersionedValue {
+            value: row.try_get("value")?,
+            version: row.try_get("version")?,
+        };
+        let occurred_at =
+            Self::record_event_in(conn, id, CounterEventKind::Increment, Some(amount), Some(new_value.value), reason)
+                .await?;
+        Self::add_to_rollups_in(conn, id, amount, occurred_at).await?;
+
+        if let Some(bucket) = HistogramBucket::index_of(amount) {
+            sqlx::query(
+                "INSERT INTO counter_histograms (counter_id, bucket, count) VALUES (?, ?, 1)
+                 ON CONFLICT(counter_id, bucket) DO UPDATE SET count = count + 1"
+            )
+            .bind(id)
+            .bind(bucket)
+            .execute(&mut *conn)
+            .await?;
+        }
+        if new_value.value > previous {
+            Self::count_increase_in(conn, id, occurred_at).await?;
+        }
+
+        Ok(new_value)
+    }
+
+    /// Counts a write that raised a counter's value in its increment rate
+    ///
+    /// The statistics triggers count every update raising a counter's value as
+    /// an increment, sets included. Every write calls this when they would,
+    /// so `increment_rate` counts the same changes as `total_increments`.
+    async fn count_increase_in(conn: &mut SqliteConnection, id: &str, occurred_at: i64) -> Result<()> {
+        let (rate, rate_updated_at): (f64, Option<i64>) =
+            sqlx::query_as("SELECT increment_rate, rate_updated_at FROM counters WHERE id = ?")
+                .bind(id)
+                .fetch_one(&mut *conn)
+                .await?;
+
+        let rate = decayed_rate(rate, rate_updated_at, occurred_at) + 1.0 / RATE_TIME_CONSTANT_MINUTES;
+        sqlx::query("UPDATE counters SET increment_rate = ?, rate_updated_at = ? WHERE id = ?")
+            .bind(rate)
+            .bind(occurred_at)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        Ok(())
+    }
+
+    /// Adds an increment to the counter's rollup bucket of every granularity
+    ///
+    /// Bucket totals saturate at the `i64` limits instead of overflowing.
+    async fn add_to_rollups_in(conn: &mut SqliteConnection, id: &str, amount: i64, occurred_at: i64) -> Result<()> {
+        for granularity in Granularity::ALL {
+            sqlx::query(
+                "INSERT INTO counter_rollups (counter_id, granularity, bucket_start, total, increments)
+                 VALUES (?, ?, ?, ?, 1)
+                 ON CONFLICT(counter_id, granularity, bucket_start) DO UPDATE SET
+                     total = CAST(MAX(MIN(total + excluded.total, 9223372036854775807), -9223372036854775808) AS INTEGER),
+                     increments = increments + 1"
+            )
+            .bind(id)
+            .bind(granularity)
+            .bind(granularity.bucket_start(occurred_at))
+            .bind(amount)
+            .execute(&mut *conn)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Appends a change of a counter to its history in `counter_events`
+    ///
+    /// Must run in the same transaction as the change itself, so the history
+    /// never disagrees with the stored value. Returns the time the event was
+    /// recorded at, in milliseconds since the Unix epoch.
+    async fn record_event_in(
+        conn: &mut SqliteConnection,
+        id: &str,
+        kind: CounterEventKind,
+        amount: Option<i64>,
+        value: Option<i64>,
+        reason: Option<&str>,
+    ) -> Result<i64> {
+        let event = sqlx::query(
+            "INSERT INTO counter_events (counter_id, kind, amount, value, reason) VALUES (?, ?, ?, ?, ?)
+             RETURNING id, occurred_at"
+        )
+        .bind(id)
+        .bind(kind)
+        .bind(amount)
+        .bind(value)
+        .bind(reason)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        // Take a checkpoint once enough events have piled up since the last one
+        let event_id: i64 = event.try_get("id")?;
+        let occurred_at: i64 = event.try_get("occurred_at")?;
+        let since_checkpoint: i64 = sqlx::query_scalar(
+            "INSERT INTO counter_checkpoint_progress (counter_id, events_since_checkpoint) VALUES (?, 1)
+             ON CONFLICT (counter_id) DO UPDATE SET events_since_checkpoint = events_since_checkpoint + 1
+             RETURNING events_since_checkpoint"
+        )
+        .bind(id)
+        .fetch_one(&mut *conn)
+        .await?;
+
+        if since_checkpoint >= CHECKPOINT_INTERVAL {
+            sqlx::query("INSERT INTO counter_checkpoints (counter_id, event_id, value, occurred_at) VALUES (?, ?, ?, ?)")
+                .bind(id)
+                .bind(event_id)
+                .bind(value)
+                .bind(occurred_at)
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query("UPDATE counter_checkpoint_progress SET events_since_checkpoint = 0 WHERE counter_id = ?")
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+        }
+
+        Ok(occurred_at)
+    }
+
+    /// Returns the version a counter created under `id` starts at
+    ///
+    /// This is 1 for a new ID, or one past the last version of the counter
+    /// that was deleted under it, so a compare-and-set holding a version of the
+    /// deleted counter can never succeed against the new one.
+    async fn initial_version_in(conn: &mut SqliteConnection, id: &str) -> Result<i64> {
+        let retired: Option<i64> = sqlx::query_scalar("SELECT version FROM counter_tombstones WHERE counter_id = ?")
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        Ok(retired.map_or(1, |version| version + 1))
+    }
+
+    /// Checks that setting a counter to `value` keeps it within its bounds
+    ///
+    /// Counters that don't exist yet have no bounds.
+    async fn check_bounds_in(conn: &mut SqliteConnection, id: &str, value: i64) -> Result<()> {
+        let row = sqlx::query("SELECT min_value, max_value FROM counters WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *conn)
+            .await?;
+
+        if let Some(row) = row {
+            let bounds = CounterBounds {
+                min_value: row.try_get("min_value")?,
+                max_value: row.try_get("max_value")?,
+            };
+            if !bounds.contains(value) {
+                return Err(bounds_violation(id, value, bounds));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new counter with an initial value, description and bounds
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter to create
+    /// * `value` - The counter's initial value, which must lie within `bounds`
+    /// * `description` - Optional human readable description
+    /// * `bounds` - Limits enforced on every later change of the counter
+    /// * `expires_at` - Optional expiry time in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The new counter's value and version, or `DatabaseError::AlreadyExists`
+    /// if a counter with that ID already exists and has not expired
+    pub async fn create_counter(
+        &self,
+        id: &str,
+        value: i64,
+        description: Option<&str>,
+        bounds: CounterBounds,
+        expires_at: Option<i64>,
+    ) -> Result<VersionedValue> {
+        if !bounds.is_valid() {
+            return Err(DatabaseError::ConstraintViolation {
+                message: format!("counter '{}' cannot have min_value above max_value", id),
+            });
+        }
+        if !bounds.contains(value) {
+            return Err(bounds_violation(id, value, bounds));
+        }
+
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let version = Self::initial_version_in(&mut tx, id).await?;
+        let row = sqlx::query(
+            "INSERT INTO counters (id, value, description, min_value, max_value, expires_at, version)
+             VALUES (?, ?, ?, ?, ?, ?, ?)
+             ON CONFLICT(id) DO NOTHING
+             RETURNING value, version"
+        )
+        .bind(id)
+        .bind(value)
+        .bind(description)
+        .bind(bounds.min_value)
+        .bind(bounds.max_value)
+        .bind(expires_at)
+        .bind(version)
+        .fetch_optional(&mut *tx)
+        .await?;
+
+        let Some(row) = row else {
+            return Err(DatabaseError::AlreadyExists { id: id.to_string() });
+        };
+        let created = VersionedValue {
+            value: row.try_get("value")?,
+            version: row.try_get("version")?,
+        };
+        Self::record_event_in(&mut tx, id, CounterEventKind::Set, None, Some(created.value), None).await?;
+        tx.commit().await?;
+
+        self.publish_expiry(id, expired);
+        self.publish(id, Some(created.value));
+        Ok(created)
+    }
+
+    /// Sets the overflow policy of an existing counter
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter
+    /// * `policy` - The policy applied to future increments
+    ///
+    /// # Returns
+    ///
+    /// true if the policy was set, false if no counter with that ID exists
+    pub async fn set_overflow_policy(&self, id: &str, policy: OverflowPolicy) -> Result<bool> {
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let result = sqlx::query("UPDATE counters SET overflow_policy = ? WHERE id = ?")
+            .bind(policy.as_str())
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.publish_expiry(id, expired);
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Sets or removes the quota of an existing counter
+    ///
+    /// A quota with a period starts its first period now; the counter keeps
+    /// its current value until the next period begins.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter
+    /// * `quota` - The quota enforced on future increments; a quota without
+    ///   a limit removes it
+    ///
+    /// # Returns
+    ///
+    /// true if the quota was set, false if no counter with that ID exists
+    pub async fn set_quota(&self, id: &str, quota: CounterQuota) -> Result<bool> {
+        if quota.period.is_some() && quota.limit.is_none() {
+            return Err(DatabaseError::ConstraintViolation {
+                message: format!("the quota of counter '{}' needs a limit to have a period", id),
+            });
+        }
+
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let bounds = sqlx::query_as::<_, CounterBounds>("SELECT min_value, max_value FROM counters WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(bounds) = bounds else {
+            tx.commit().await?;
+            self.publish_expiry(id, expired);
+            return Ok(false);
+        };
+        if quota.period.is_some() && !bounds.contains(0) {
+            return Err(DatabaseError::ConstraintViolation {
+                message: format!("counter '{}' cannot be reset to 0 as it must be {}", id, bounds),
+            });
+        }
+
+        sqlx::query("UPDATE counters SET quota_limit = ?, quota_period = ?, quota_period_start = ? WHERE id = ?")
+            .bind(quota.limit)
+            .bind(quota.period)
+            .bind(quota.period.map(|period| period.period_start(now_millis())))
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Resets a quota counter to zero if a new quota period has begun by `now`
+    ///
+    /// The reset is recorded in the counter's history as a set with the
+    /// reason "quota period reset".
+    ///
+    /// # Returns
+    ///
+    /// true if the counter was reset
+    async fn roll_quota_period_in(conn: &mut SqliteConnection, id: &str, now: i64) -> Result<bool> {
+        let quota = sqlx::query_as::<_, (Option<QuotaPeriod>, Option<i64>, i64)>(
+            "SELECT quota_period, quota_period_start, value FROM counters WHERE id = ?"
+        )
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await?;
+
+        let Some((Some(period), started, previous)) = quota else {
+            return Ok(false);
+        };
+        let period_start = period.period_start(now);
+        if started.is_some_and(|started| started >= period_start) {
+            return Ok(false);
+        }
+
+        sqlx::query("UPDATE counters SET value = 0, version = version + 1, quota_period_start = ? WHERE id = ?")
+            .bind(period_start)
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+        let occurred_at =
+            Self::record_event_in(conn, id, CounterEventKind::Set, None, Some(0), Some("quota period reset")).await?;
+        if previous < 0 {
+            Self::count_increase_in(conn, id, occurred_at).await?;
+        }
+        Ok(true)
+    }
+
+    /// Resets every quota counter whose quota period has ended by `now`
+    ///
+    /// Increments reset a counter themselves when they are the first of a new
+    /// period; the `ResetScheduler` calls this every tick, so reads reflect the
+    /// new period without waiting for one.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the counters that were reset
+    pub async fn reset_quota_periods(&self, now: i64) -> Result<Vec<String>> {
+        // Most passes find nothing to reset, so look without taking the write lock
+        let quotas = sqlx::query_as::<_, (String, QuotaPeriod, Option<i64>)>(
+            "SELECT id, quota_period, quota_period_start FROM counters WHERE quota_period IS NOT NULL ORDER BY id"
+        )
+        .fetch_all(&*self.pool)
+        .await?;
+        let due: Vec<String> = quotas
+            .into_iter()
+            .filter(|(_, period, started)| !started.is_some_and(|started| started >= period.period_start(now)))
+            .map(|(id, _, _)| id)
+            .collect();
+        if due.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Rolling a counter over checks again, as it may have changed since
+        let mut tx = self.begin_write().await?;
+        let mut reset = Vec::new();
+        for id in due {
+            if Self::roll_quota_period_in(&mut tx, &id, now).await? {
+                reset.push(id);
+            }
+        }
+        tx.commit().await?;
+
+        for id in &reset {
+            self.publish(id, Some(0));
+        }
+        Ok(reset)
+    }
+
+    /// Sets the schedule on which a counter is reset to `reset_value`
+    ///
+    /// A new period starts now, replacing any previous schedule; the counter
+    /// keeps its value until the first reset. Resets are applied by
+    /// `apply_due_resets`, which archives the final value of each period.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter
+    /// * `schedule` - When to reset the counter
+    /// * `reset_value` - The value to reset it to, which must lie within its bounds
+    ///
+    /// # Returns
+    ///
+    /// The stored schedule, or `None` if no counter with that ID exists
+    pub async fn set_reset_schedule(
+        &self,
+        id: &str,
+        schedule: &CronSchedule,
+        reset_value: i64,
+    ) -> Result<Option<ResetSchedule>> {
+        let now = now_millis();
+        let Some(next_reset_at) = schedule.next_after(now) else {
+            return Err(DatabaseError::ConstraintViolation {
+                message: format!("schedule '{}' never resets counter '{}'", schedule, id),
+            });
+        };
+
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let bounds = sqlx::query_as::<_, CounterBounds>("SELECT min_value, max_value FROM counters WHERE id = ?")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(bounds) = bounds else {
+            tx.commit().await?;
+            self.publish_expiry(id, expired);
+            return Ok(None);
+        };
+        if !bounds.contains(reset_value) {
+            return Err(bounds_violation(id, reset_value, bounds));
+        }
+
+        let stored = sqlx::query_as::<_, ResetSchedule>(
+            "INSERT INTO counter_reset_schedules (counter_id, schedule, reset_value, period_start, next_reset_at)
+             VALUES (?, ?, ?, ?, ?)
+             ON CONFLICT (counter_id) DO UPDATE SET
+                 schedule = excluded.schedule,
+                 reset_value = excluded.reset_value,
+                 period_start = excluded.period_start,
+                 next_reset_at = excluded.next_reset_at
+             RETURNING counter_id, schedule, reset_value, period_start, next_reset_at"
+        )
+        .bind(id)
+        .bind(schedule.to_string())
+        .bind(reset_value)
+        .bind(now)
+        .bind(next_reset_at)
+        .fetch_one(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(Some(stored))
+    }
+
+    /// Removes a counter's reset schedule
+    ///
+    /// # Returns
+    ///
+    /// true if the counter had a reset schedule
+    pub async fn remove_reset_schedule(&self, id: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM counter_reset_schedules WHERE counter_id = ?")
+            .bind(id)
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected() > 0)
+    }
+
+    /// Gets a counter's reset schedule, if it has one
+    pub async fn get_reset_schedule(&self, id: &str) -> Result<Option<ResetSchedule>> {
+        let schedule = sqlx::query_as::<_, ResetSchedule>(
+            "SELECT counter_id, schedule, reset_value, period_start, next_reset_at
+             FROM counter_reset_schedules WHERE counter_id = ?"
+        )
+        .bind(id)
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        Ok(schedule)
+    }
+
+    /// Applies every scheduled reset that is due by `now`
+    ///
+    /// Before a counter is reset, its value is archived in `counter_periods`
+    /// as the final value of the period, and the reset is recorded in its
+    /// history as a set with the reason "scheduled reset". Resets missed
+    /// while the server was down are caught up on here: a counter that missed
+    /// several resets cannot have changed in between, so it is reset once,
+    /// every missed period after the first is archived with `reset_value` as
+    /// its final value, and the current period starts at the last missed reset.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the counters that were reset
+    pub async fn apply_due_resets(&self, now: i64) -> Result<Vec<String>> {
+        // Most passes find nothing due, so look without taking the write lock
+        let next_due: Option<i64> = sqlx::query_scalar("SELECT MIN(next_reset_at) FROM counter_reset_schedules")
+            .fetch_one(&*self.pool)
+            .await?;
+        if next_due.is_none_or(|next_due| next_due > now) {
+            return Ok(Vec::new());
+        }
+
+        let mut tx = self.begin_write().await?;
+        let due = sqlx::query_as::<_, ResetSchedule>(
+            "SELECT counter_id, schedule, reset_value, period_start, next_reset_at
+             FROM counter_reset_schedules
+             WHERE next_reset_at <= ?
+             ORDER BY next_reset_at, counter_id"
+        )
+        .bind(now)
+        .fetch_all(&mut *tx)
+        .await?;
+
+        let mut reset = Vec::new();
+        let mut expired = Vec::new();
+        for due in due {
+            let id = due.counter_id.as_str();
+            // Deleting an expired counter also deletes its schedule
+            if Self::expire_in(&mut tx, id).await? {
+                expired.push(due.counter_id);
+                continue;
+            }
+            let final_value: i64 = sqlx::query_scalar("SELECT value FROM counters WHERE id = ?")
+                .bind(id)
+                .fetch_one(&mut *tx)
+                .await?;
+
+            // The period ending at the first reset due closes with the counter's value. Any
+            // resets missed after it would each have found the counter at its reset value,
+            // so their periods are archived with that value, and the current period starts
+            // at the last of them.
+            let schedule: CronSchedule = due.schedule.parse().map_err(|e| DatabaseError::Internal(Box::new(e)))?;
+            let mut period_start = due.period_start;
+            let mut period_end = due.next_reset_at;
+            let mut period_value = final_value;
+            let next_reset_at = loop {
+                sqlx::query(
+                    "INSERT INTO counter_periods (counter_id, period_start, period_end, final_value, reset_at)
+                     VALUES (?, ?, ?, ?, ?)"
+                )
+                .bind(id)
+                .bind(period_start)
+                .bind(period_end)
+                .bind(period_value)
+                .bind(now)
+                .execute(&mut *tx)
+                .await?;
+
+                period_start = period_end;
+                period_value = due.reset_value;
+                match schedule.next_after(period_end) {
+                    Some(missed) if missed <= now => period_end = missed,
+                    next_reset_at => break next_reset_at,
+                }
+            };
+
+            sqlx::query("UPDATE counters SET value = ?, version = version + 1 WHERE id = ?")
+                .bind(due.reset_value)
+                .bind(id)
+                .execute(&mut *tx)
+                .await?;
+            let occurred_at = Self::record_event_in(
+                &mut tx,
+                id,
+                CounterEventKind::Set,
+                None,
+                Some(due.reset_value),
+                Some("scheduled reset"),
+            )
+            .await?;
+            if due.reset_value > final_value {
+                Self::count_increase_in(&mut tx, id, occurred_at).await?;
+            }
+
+            match next_reset_at {
+                Some(next_reset_at) => {
+                    sqlx::query(
+                        "UPDATE counter_reset_schedules SET period_start = ?, next_reset_at = ? WHERE counter_id = ?"
+                    )
+                    .bind(period_start)
+                    .bind(next_reset_at)
+                    .bind(id)
+                    .execute(&mut *tx)
+                    .await?;
+                }
+                None => {
+                    sqlx::query("DELETE FROM counter_reset_schedules WHERE counter_id = ?")
+                        .bind(id)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+            reset.push((due.counter_id, due.reset_value));
+        }
+        tx.commit().await?;
+
+        for id in &expired {
+            self.publish(id, None);
+        }
+        for (id, value) in &reset {
+            self.publish(id, Some(*value));
+        }
+        Ok(reset.into_iter().map(|(id, _)| id).collect())
+    }
+
+    /// Gets the periods of a counter that were ended by scheduled resets
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter
+    /// * `after` - End of the last period of the previous page, if any
+    /// * `limit` - Maximum number of periods to return
+    ///
+    /// # Returns
+    ///
+    /// A vector of at most `limit` periods, oldest first. The periods of a
+    /// deleted counter remain available.
+    pub async fn get_counter_periods(&self, id: &str, after: Option<i64>, limit: u32) -> Result<Vec<CounterPeriod>> {
+        let periods = sqlx::query_as::<_, CounterPeriod>(
+            "SELECT period_start, period_end, final_value, reset_at
+             FROM counter_periods
+             WHERE counter_id = ?1 AND (?2 IS NULL OR period_end > ?2)
+             ORDER BY period_end
+             LIMIT ?3"
+        )
+        .bind(id)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(periods)
+    }
+
+    /// Reserves a block of `count` consecutive IDs from a sequence
+    ///
+    /// Sequences start at 1 and are created on first use. The reservation is
+    /// committed before the block is returned, so no ID is handed out twice,
+    /// even across restarts; IDs of blocks that are never used are skipped.
+    ///
+    /// # Arguments
+    ///
+    /// * `sequence` - The name of the sequence
+    /// * `count` - The number of IDs to reserve, which must be positive
+    ///
+    /// # Returns
+    ///
+    /// The reserved block
+    pub async fn next_ids(&self, sequence: &str, count: i64) -> Result<IdBlock> {
+        if count <= 0 {
+            return Err(DatabaseError::ConstraintViolation {
+                message: format!("cannot reserve {} IDs from sequence '{}'", count, sequence),
+            });
+        }
+
+        let mut tx = self.begin_write().await?;
+        let first: i64 = sqlx::query_scalar("SELECT next_id FROM sequences WHERE name = ?")
+            .bind(sequence)
+            .fetch_optional(&mut *tx)
+            .await?
+            .unwrap_or(1);
+        let next_id = first.checked_add(count).ok_or_else(|| DatabaseError::ConstraintViolation {
+            message: format!("sequence '{}' has fewer than {} IDs left", sequence, count),
+        })?;
+
+        sqlx::query(
+            "INSERT INTO sequences (name, next_id) VALUES (?, ?)
+             ON CONFLICT (name) DO UPDATE SET next_id = excluded.next_id"
+        )
+        .bind(sequence)
+        .bind(next_id)
+        .execute(&mut *tx)
+        .await?;
+        tx.commit().await?;
+
+        Ok(IdBlock { first, count })
+    }
+
+    /// Applies a batch of increments, sets and deletes in a single transaction
+    ///
+    /// Either every operation is applied or, if any of them fails, none are.
+    /// Operations see the effects of earlier operations in the same batch.
+    ///
+    /// # Arguments
+    ///
+    /// * `operations` - The operations to apply, in order
+    ///
+    /// # Returns
+    ///
+    /// The outcome of each operation: the value and version of its counter
+    /// right after a write, or whether a delete found a counter to delete
+    pub async fn apply_batch(&self, operations: &[CounterOperation]) -> Result<Vec<OperationOutcome>> {
+        let mut tx = self.begin_write().await?;
+
+        let mut results = Vec::with_capacity(operations.len());
+        let mut changes = Vec::with_capacity(operations.len());
+        for operation in operations {
+            if Self::expire_in(&mut tx, operation.id()).await? {
+                changes.push((operation.id(), None));
+            }
+            let result = match operation {
+                CounterOperation::Increment { id, amount } => {
+                    OperationOutcome::Written(Self::increment_in(&mut tx, id, *amount, None, None).await?)
+                }
+                CounterOperation::Set { id, value } => OperationOutcome::Written(Self::set_in(&mut tx, id, *value).await?),
+                CounterOperation::Delete { id } => {
+                    if !Self::remove_in(&mut tx, id, None).await? {
+                        results.push(OperationOutcome::NotFound);
+                        continue;
+                    }
+                    OperationOutcome::Deleted
+                }
+            };
+            changes.push((operation.id(), result.written().map(|r| r.value)));
+            results.push(result);
+        }
+
+        tx.commit().await?;
+
+        for (id, value) in changes {
+            self.publish(id, value);
+        }
+        Ok(results)
+    }
+
+    /// Sets a counter to a new value only if its version still matches
+    ///
+    /// An `expected_version` of 0 means the counter must not exist yet, in
+    /// which case it is created with `new_value`.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter to set
+    /// * `expected_version` - The version the caller last read
+    /// * `new_value` - The value to store if the version matches
+    ///
+    /// # Returns
+    ///
+    /// The counter's new value and version, or `DatabaseError::Conflict`
+    /// carrying the actual version (0 if the counter doesn't exist)
+    pub async fn compare_and_set(
+        &self,
+        id: &str,
+        expected_version: i64,
+        new_value: i64,
+    ) -> Result<VersionedValue> {
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+
+        // Value before an update, which counts as an increment if it raises it
+        let mut previous: Option<i64> = None;
+        let row = if expected_version == 0 {
+            let version = Self::initial_version_in(&mut tx, id).await?;
+            sqlx::query(
+                "INSERT INTO counters (id, value, version) VALUES (?, ?, ?)
+                 ON CONFLICT(id) DO NOTHING
+                 RETURNING value, version"
+            )
+            .bind(id)
+            .bind(new_value)
+            .bind(version)
+            .fetch_optional(&mut *tx)
+            .await?
+        } else {
+            Self::check_bounds_in(&mut tx, id, new_value).await?;
+            previous = sqlx::query_scalar("SELECT value FROM counters WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?;
+            sqlx::query(
+                "UPDATE counters SET value = ?, version = version + 1
+                 WHERE id = ? AND version = ?
+                 RETURNING value, version"
+            )
+            .bind(new_value)
+            .bind(id)
+            .bind(expected_version)
+            .fetch_optional(&mut *tx)
+            .await?
+        };
+
+        let Some(row) = row else {
+            let current_version = sqlx::query("SELECT version FROM counters WHERE id = ?")
+                .bind(id)
+                .fetch_optional(&mut *tx)
+                .await?
+                .map(|row| row.try_get("version"))
+                .transpose()?
+                .unwrap_or(0);
+            return Err(DatabaseError::Conflict {
+                id: id.to_string(),
+                expected_version,
+                current_version,
+            });
+        };
+
+        let applied = VersionedValue {
+            value: row.try_get("value")?,
+            version: row.try_get("version")?,
+        };
+        let occurred_at =
+            Self::record_event_in(&mut tx, id, CounterEventKind::Set, None, Some(applied.value), None).await?;
+        if previous.is_some_and(|previous| applied.value > previous) {
+            Self::count_increase_in(&mut tx, id, occurred_at).await?;
+        }
+        tx.commit().await?;
+
+        self.publish_expiry(id, expired);
+        self.publish(id, Some(applied.value));
+        Ok(applied)
+    }
+
+    /// Lists all counters in the database
+    ///
+    /// # Returns
+    ///
+    /// A vector of the counters that have not expired, ordered by ID
+    pub async fn list_counters(&self) -> Result<Vec<Counter>> {
+        let query = format!("SELECT {} FROM counters WHERE {} ORDER BY id", COUNTER_COLUMNS, NOT_EXPIRED);
+        let counters = sqlx::query_as::<_, Counter>(&query)
+            .bind(now_millis())
+            .fetch_all(&*self.pool)
+            .await?;
+
+        Ok(counters)
+    }
+
+    /// Lists one page of unexpired counters whose IDs start with `prefix`
+    ///
+    /// Uses keyset pagination, so the cost of fetching a page does not grow
+    /// with the number of counters before it.
+    ///
+    /// # Arguments
+    ///
+    /// * `prefix` - Only counters whose ID starts with this prefix are returned
+    /// * `order` - Column to order the counters by
+    /// * `descending` - Whether to list the counters in descending order
+    /// * `after` - Cursor of the last counter of the previous page, if any
+    /// * `limit` - Maximum number of counters to return
+    ///
+    /// # Returns
+    ///
+    /// A vector of at most `limit` counters
+    pub async fn list_counters_page(
+        &self,
+        prefix: &str,
+        order: CounterOrder,
+        descending: bool,
+        after: Option<&CounterCursor>,
+        limit: u32,
+    ) -> Result<Vec<Counter>> {
+        let mut query = QueryBuilder::<Sqlite>::new(format!("SELECT {} FROM counters WHERE ", COUNTER_COLUMNS));
+        query
+            .push("(expires_at IS NULL OR expires_at > ")
+            .push_bind(now_millis())
+            .push(")");
+        if !prefix.is_empty() {
+            // A range on the ID rather than a function of it, so the primary key index can serve it
+            query
+                .push(" AND id >= ")
+                .push_bind(prefix)
+                .push(" AND id < ")
+                .push_bind(format!("{}{}", prefix, char::MAX));
+        }
+
+        let cmp = if descending { " < " } else { " > " };
+        if let Some(cursor) = after {
+            match order {
+                CounterOrder::Id => {
+                    query.push(" AND id").push(cmp).push_bind(cursor.id.as_str());
+                }
+                CounterOrder::Value => {
+                    query
+                        .push(" AND (value")
+                        .push(cmp)
+                        .push_bind(cursor.value)
+                        .push(" OR (value = ")
+                        .push_bind(cursor.value)
+                        .push(" AND id")
+                        .push(cmp)
+                        .push_bind(cursor.id.as_str())
+                        .push("))");
+                }
+            }
+        }
+
+        let direction = if descending { "DESC" } else { "ASC" };
+        match order {
+            CounterOrder::Id => query.push(format!(" ORDER BY id {}", direction)),
+            CounterOrder::Value => {
+                query.push(format!(" ORDER BY value {0}, id {0}", direction))
+            }
+        };
+        query.push(" LIMIT ").push_bind(limit);
+
+        let counters = query.build_query_as::<Counter>().fetch_all(&*self.pool).await?;
+
+        Ok(counters)
+    }
+
+    /// Gets detailed statistics for a counter from the `counter_stats` view
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter to get statistics for
+    ///
+    /// # Returns
+    ///
+    /// The counter's statistics, or `None` if the counter doesn't exist or has expired
+    pub async fn get_counter_stats(&self, id: &str) -> Result<Option<CounterStats>> {
+        let stats = sqlx::query_as::<_, CounterStats>(
+            "SELECT id, current_value, total_increments, average_increment, highest_value,
+                    total_decrements, lowest_value, last_increment_at, last_decrement_at,
+                    increment_rate, rate_updated_at, description,
+                    CAST(strftime('%s', created_at) AS INTEGER) AS created_at,
+                    CAST(strftime('%s', updated_at) AS INTEGER) AS updated_at
+             FROM counter_stats WHERE id = ? AND (expires_at IS NULL OR expires_at > ?)"
+        )
+        .bind(id)
+        .bind(now_millis())
+        .fetch_optional(&*self.pool)
+        .await?;
+
+        let Some(mut stats) = stats else {
+            return Ok(None);
+        };
+        stats.increments_per_minute = decayed_rate(stats.increments_per_minute, stats.rate_updated_at, now_millis());
+        stats.increment_histogram.buckets = sqlx::query_as::<_, HistogramBucket>(
+            "SELECT bucket, count FROM counter_histograms WHERE counter_id = ? ORDER BY bucket"
+        )
+        .bind(id)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(Some(stats))
+    }
+
+    /// Gets the increments applied to a counter, bucketed by time
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter
+    /// * `granularity` - Size of the buckets
+    /// * `from` - Start of the range in milliseconds since the Unix epoch,
+    ///   rounded down to the start of its bucket
+    /// * `to` - End of the range in milliseconds since the Unix epoch (exclusive)
+    ///
+    /// # Returns
+    ///
+    /// One bucket per `granularity` step from `from` up to `to`, in order.
+    /// Buckets without increments are included with zero totals.
+    pub async fn get_counter_series(
+        &self,
+        id: &str,
+        granularity: Granularity,
+        from: i64,
+        to: i64,
+    ) -> Result<Vec<SeriesBucket>> {
+        let start = granularity.bucket_start(from);
+        let rows = sqlx::query_as::<_, SeriesBucket>(
+            "SELECT bucket_start, total, increments FROM counter_rollups
+             WHERE counter_id = ? AND granularity = ? AND bucket_start >= ? AND bucket_start < ?
+             ORDER BY bucket_start"
+        )
+        .bind(id)
+        .bind(granularity)
+        .bind(start)
+        .bind(to)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        // Fill the gaps between stored buckets with empty ones
+        let mut rows = rows.into_iter().peekable();
+        let mut series = Vec::new();
+        let mut bucket_start = start;
+        while bucket_start < to {
+            match rows.next_if(|row| row.bucket_start == bucket_start) {
+                Some(row) => series.push(row),
+                None => series.push(SeriesBucket { bucket_start, total: 0, increments: 0 }),
+            }
+            bucket_start = match bucket_start.checked_add(granularity.millis()) {
+                Some(next) => next,
+                None => break,
+            };
+        }
+
+        Ok(series)
+    }
+
+    /// Deletes a counter by ID
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter to delete
+    ///
+    /// # Returns
+    ///
+    /// true if a counter was deleted, false if no counter with that ID existed
+    /// or it had already expired
+    pub async fn delete_counter(&self, id: &str) -> Result<bool> {
+        let mut tx = self.begin_write().await?;
+        let expired = Self::expire_in(&mut tx, id).await?;
+        let deleted = Self::remove_in(&mut tx, id, None).await?;
+        tx.commit().await?;
+
+        if expired || deleted {
+            self.publish(id, None);
+        }
+        Ok(deleted)
+    }
+
+    /// Deletes the counter if it has expired, recording why in its history
+    ///
+    /// Every write calls this before touching a counter, and once its
+    /// transaction has committed tells watchers about the deletion with
+    /// `publish_expiry`.
+    ///
+    /// # Returns
+    ///
+    /// true if an expired counter was deleted
+    async fn expire_in(conn: &mut SqliteConnection, id: &str) -> Result<bool> {
+        let expired = sqlx::query("SELECT 1 FROM counters WHERE id = ? AND expires_at <= ?")
+            .bind(id)
+            .bind(now_millis())
+            .fetch_optional(&mut *conn)
+            .await?
+            .is_some();
+        if expired {
+            Self::remove_in(conn, id, Some("expired")).await?;
+        }
+        Ok(expired)
+    }
+
+    /// Removes a counter with its histogram and reset schedule, recording a delete event
+    async fn remove_in(conn: &mut SqliteConnection, id: &str, reason: Option<&str>) -> Result<bool> {
+        // Remember the last version, for a counter recreated under this ID to carry on from
+        sqlx::query(
+            "INSERT INTO counter_tombstones (counter_id, version) SELECT id, version FROM counters WHERE id = ?
+             ON CONFLICT (counter_id) DO UPDATE SET version = excluded.version"
+        )
+        .bind(id)
+        .execute(&mut *conn)
+        .await?;
+
+        let result = sqlx::query("DELETE FROM counters WHERE id = ?")
+            .bind(id)
+            .execute(&mut *conn)
+            .await?;
+
+        let deleted = result.rows_affected() > 0;
+        if deleted {
+            sqlx::query("DELETE FROM counter_histograms WHERE counter_id = ?")
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+            sqlx::query("DELETE FROM counter_reset_schedules WHERE counter_id = ?")
+                .bind(id)
+                .execute(&mut *conn)
+                .await?;
+            Self::record_event_in(conn, id, CounterEventKind::Delete, None, None, reason).await?;
+        }
+        Ok(deleted)
+    }
+
+    /// Deletes every counter that has expired by `now`
+    ///
+    /// Expired counters already read as absent; this reclaims their storage
+    /// and tells watchers they are gone. Each deletion is recorded in the
+    /// counter's history with the reason "expired".
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the deleted counters
+    pub async fn purge_expired_counters(&self, now: i64) -> Result<Vec<String>> {
+        let mut tx = self.begin_write().await?;
+        let expired: Vec<String> = sqlx::query_scalar("SELECT id FROM counters WHERE expires_at <= ? ORDER BY id")
+            .bind(now)
+            .fetch_all(&mut *tx)
+            .await?;
+        for id in &expired {
+            Self::remove_in(&mut tx, id, Some("expired")).await?;
+        }
+        tx.commit().await?;
+
+        for id in &expired {
+            self.publish(id, None);
+        }
+        Ok(expired)
+    }
+
+    /// Tries to acquire permits from a key's rate limit
+    ///
+    /// Uses the generic cell rate algorithm: permits are replenished evenly,
+    /// one every `window / limit`, and at most `limit` permits are available
+    /// at once. This behaves like a sliding window of length `window` while
+    /// storing a single timestamp per key.
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key to rate limit
+    /// * `permits` - Number of permits to acquire, between 1 and `limit`
+    /// * `limit` - Maximum number of permits granted per window
+    /// * `window` - Length of the window in milliseconds
+    ///
+    /// # Returns
+    ///
+    /// Whether the permits were granted and, if not, how long to wait before
+    /// retrying. Denied requests do not consume any permits.
+    pub async fn try_acquire(&self, key: &str, permits: i64, limit: i64, window: i64) -> Result<RateLimitDecision> {
+        self.try_acquire_at(key, permits, limit, window, now_millis()).await
+    }
+
+    /// Tries to acquire permits as of `now`, see `try_acquire`
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in milliseconds since the Unix epoch
+    pub async fn try_acquire_at(
+        &self,
+        key: &str,
+        permits: i64,
+        limit: i64,
+        window: i64,
+        now: i64,
+    ) -> Result<RateLimitDecision> {
+        if permits < 1 || limit < 1 || permits > limit || window < 1 {
+            return Err(DatabaseError::ConstraintViolation {
+                message: format!(
+                    "cannot acquire {} permits from a limit of {} per {}ms",
+                    permits, limit, window
+                ),
+            });
+        }
+
+        // Microseconds keep the emission interval exact enough for high limits
+        let overflow = || DatabaseError::ConstraintViolation {
+            message: format!("rate limit window of {}ms is too long", window),
+        };
+        let now = now.checked_mul(1000).ok_or_else(overflow)?;
+        let window = window.checked_mul(1000).ok_or_else(overflow)?;
+        let interval = window / limit + i64::from(window % limit != 0);
+
+        let mut tx = self.begin_write().await?;
+        let stored: Option<i64> = sqlx::query_scalar("SELECT theoretical_arrival FROM rate_limits WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+        let arrival = stored.map_or(now, |arrival| arrival.max(now));
+        let new_arrival = arrival.saturating_add(permits.saturating_mul(interval));
+        let allow_at = new_arrival.saturating_sub(window);
+
+        let decision = if allow_at <= now {
+            sqlx::query(
+                "INSERT INTO rate_limits (key, theoretical_arrival) VALUES (?, ?)
+                 ON CONFLICT(key) DO UPDATE SET theoretical_arrival = excluded.theoretical_arrival"
+            )
+            .bind(key)
+            .bind(new_arrival)
+            .execute(&mut *tx)
+            .await?;
+            RateLimitDecision {
+                allowed: true,
+                remaining: ((window - (new_arrival - now)) / interval).clamp(0, limit),
+                retry_after: 0,
+            }
+        } else {
+            let wait = allow_at - now;
+            RateLimitDecision {
+                allowed: false,
+                // A key used with a larger limit or longer window can be further ahead than this window
+                remaining: ((window - (arrival - now)) / interval).clamp(0, limit),
+                retry_after: wait / 1000 + i64::from(wait % 1000 != 0),
+            }
+        };
+        tx.commit().await?;
+
+        Ok(decision)
+    }
+
+    /// Deletes the rate limit state of keys that have fully replenished by `now`
+    ///
+    /// Such keys behave exactly as if they had never been used.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The number of keys whose state was deleted
+    pub async fn purge_idle_rate_limits(&self, now: i64) -> Result<u64> {
+        let result = sqlx::query("DELETE FROM rate_limits WHERE theoretical_arrival <= ?")
+            .bind(now.saturating_mul(1000))
+            .execute(&*self.pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+
+    /// Lists the recorded history of a counter, oldest event first
+    ///
+    /// Uses keyset pagination on the event ID, which increases in the order
+    /// events were recorded.
+    ///
+    /// # Arguments
+    ///
+    /// * `id` - The ID of the counter
+    /// * `from` - Only events at or after this time (milliseconds since the Unix epoch)
+    /// * `to` - Only events before this time (milliseconds since the Unix epoch)
+    /// * `after` - ID of the last event of the previous page, if any
+    /// * `limit` - Maximum number of events to return
+    ///
+    /// # Returns
+    ///
+    /// A vector of at most `limit` events. The history of a deleted counter
+    /// remains available.
+    pub async fn get_counter_history(
+        &self,
+        id: &str,
+        from: Option<i64>,
+        to: Option<i64>,
+        after: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<CounterEvent>> {
+        let events = sqlx::query_as::<_, CounterEvent>(
+            "SELECT id, counter_id, kind, amount, value, reason, occurred_at
+             FROM counter_events
+             WHERE counter_id = ?1
+               AND (?2 IS NULL OR occurred_at >= ?2)
+               AND (?3 IS NULL OR occurred_at < ?3)
+               AND (?4 IS NULL OR id > ?4)
+             ORDER BY id
+             LIMIT ?5"
+        )
+        .bind(id)
+        .bind(from)
+        .bind(to)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&*self.pool)
+        .await?;
+
+        Ok(events)
+    }
+
+    /// Starts a transaction that takes the database write lock up front
+    ///
+    /// Transactions that read a counter before writing it must use this, so
+    /// that concurrent writers queue up on the lock instead of failing when
+    /// they try to upgrade a read lock.
+    async fn begin_write(&self) -> Result<Transaction<'static, Sqlite>> {
+        Ok(self.pool.begin_with("BEGIN IMMEDIATE").await?)
+    }
+
+    /// Subscribes to changes made to any counter through this `Database`
+    ///
+    /// The receiver sees every change committed after this call. A receiver
+    /// that falls more than `CHANGE_CHANNEL_CAPACITY` changes behind gets a
+    /// `RecvError::Lagged` and resumes with the oldest change still buffered.
+    pub fn subscribe(&self) -> broadcast::Receiver<CounterChange> {
+        self.changes.subscribe()
+    }
+
+    /// Publishes a committed change to all current subscribers
+    fn publish(&self, id: &str, value: Option<i64>) {
+        // Sending only fails when nobody is subscribed, which is fine
+        let _ = self.changes.send(CounterChange {
+            id: id.to_string(),
+            value,
+        });
+    }
+
+    /// Tells watchers that a counter was deleted if `expire_in` found it expired
+    fn publish_expiry(&self, id: &str, expired: bool) {
+        if expired {
+            self.publish(id, None);
+        }
+    }
+    
+    /// Returns a reference to the underlying connection pool
+    pub fn pool(&self) -> &SqlitePool {
+        &self.pool
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_counter_operations() -> Result<()> {
+        // Use an in-memory database for testing
+        let db = Database::connect("sqlite::memory:").await?;
+
+        // Test getting a non-existent counter (should fail without creating it)
+        let err = db.get_counter("test_counter").await.unwrap_err();
+        assert!(matches!(err, DatabaseError::NotFound { id } if id == "test_counter"));
+        assert!(db.get_counter_stats("test_counter").await?.is_none());
+
+        // Test explicitly creating it on read
+        assert_eq!(db.get_or_create_counter("test_counter").await?.value, 0);
+        assert_eq!(db.get_or_create_counter("test_counter").await?.value, 0);
+
+        // Test incrementing the counter
+        assert_eq!(db.increment_counter("test_counter", 5).await?.value, 5);
+
+        // Test getting the counter after increment
+        assert_eq!(db.get_counter("test_counter").await?.value, 5);
+
+        // Test incrementing again
+        assert_eq!(db.increment_counter("test_counter", 3).await?.value, 8);
+
+        // Test setting the counter directly
+        db.set_counter("test_counter", 10).await?;
+        assert_eq!(db.get_counter("test_counter").await?.value, 10);
+
+        // Test listing counters
+        let counters = db.list_counters().await?;
+        assert!(!counters.is_empty());
+        
+        // The main counter should exist (from ensure_main_counter)
+        assert!(counters.iter().any(|c| c.id == MAIN_COUNTER_ID));
+        
+        // Our test counter should exist with value 10
+        assert!(counters.iter().any(|c| c.id == "test_counter" && c.value == 10));
+
+        // Test deleting the counter
+        assert!(db.delete_counter("test_counter").await?);
+
+        // After deletion, getting it should fail
+        assert!(matches!(db.get_counter("test_counter").await, Err(DatabaseError::NotFound { .. })));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_increment_updates_stats_and_keeps_metadata() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        // The first increment creates the counter and counts as an increment
+        assert_eq!(db.increment_counter("stats_counter", 5).await?.value, 5);
+
+        // Give the counter a description and an old creation time
+        sqlx::query(
+            "UPDATE counters SET description = 'Stats test', created_at = '2000-01-01 00:00:00'
+             WHERE id = ?"
+        )
+        .bind("stats_counter")
+        .execute(db.pool())
+        .await?;
+
+        assert_eq!(db.increment_counter("stats_counter", 3).await?.value, 8);
+        assert_eq!(db.increment_counter("stats_counter", -2).await?.value, 6);
+
+        let stats = db.get_counter_stats("stats_counter").await?.unwrap();
+        assert_eq!(stats.current_value, 6);
+        assert_eq!(stats.total_increments, 2);
+        assert_eq!(stats.average_increment, 4.0);
+        assert_eq!(stats.highest_value, 8);
+        assert_eq!(stats.total_decrements, 1);
+        assert_eq!(stats.lowest_value, 0);
+        assert!(stats.last_increment_at.is_some());
+        assert!(stats.last_decrement_at >= stats.last_increment_at);
+        assert!(stats.increments_per_minute > 0.0);
+        assert_eq!(stats.description.as_deref(), Some("Stats test"));
+        assert_eq!(stats.created_at, 946684800);
+        assert!(stats.updated_at > stats.created_at);
+
+        // Setting the value keeps the metadata as well, and counts as a decrement
+        db.set_counter("stats_counter", -4).await?;
+        let stats = db.get_counter_stats("stats_counter").await?.unwrap();
+        assert_eq!(stats.current_value, -4);
+        assert_eq!(stats.total_increments, 2);
+        assert_eq!(stats.total_decrements, 2);
+        assert_eq!(stats.lowest_value, -4);
+        assert_eq!(stats.description.as_deref(), Some("Stats test"));
+        assert_eq!(stats.created_at, 946684800);
+
+        // Overflowing increments are rejected and leave the counter untouched
+        db.set_counter("stats_counter", i64::MAX).await?;
+        assert!(db.increment_counter("stats_counter", 1).await.is_err());
+        assert_eq!(db.get_counter("stats_counter").await?.value, i64::MAX);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counters_hold_64_bit_values() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        // Values well past the 32-bit range round-trip unchanged
+        let big = i64::from(i32::MAX) * 4;
+        assert_eq!(db.increment_counter("big", big).await?.value, big);
+        assert_eq!(db.increment_counter("big", big).await?.value, big * 2);
+        let stats = db.get_counter_stats("big").await?.unwrap();
+        assert_eq!(stats.highest_value, big * 2);
+
+        // Both ends of the 64-bit range are guarded against overflow
+        db.set_counter("big", i64::MIN + 1).await?;
+        assert_eq!(db.increment_counter("big", -1).await?.value, i64::MIN);
+        assert!(db.increment_counter("big", -1).await.is_err());
+        assert_eq!(db.get_counter("big").await?.value, i64::MIN);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_increments_are_not_lost() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        let tasks: Vec<_> = (0..20)
+            .map(|_| {
+                let db = db.clone();
+                tokio::spawn(async move { db.increment_counter("contended", 1).await })
+            })
+            .collect();
+        for task in tasks {
+            task.await??;
+        }
+
+        assert_eq!(db.get_counter("contended").await?.value, 20);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_bounded_counters() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        let bounds = CounterBounds { min_value: Some(0), max_value: Some(10) };
+
+        assert_eq!(db.create_counter("stock", 5, Some("Stock"), bounds, None).await?.value, 5);
+        assert!(matches!(
+            db.create_counter("stock", 1, None, bounds, None).await,
+            Err(DatabaseError::AlreadyExists { .. })
+        ));
+        let stats = db.get_counter_stats("stock").await?.unwrap();
+        assert_eq!(stats.description.as_deref(), Some("Stock"));
+        let counter = db.get_counter("stock").await?;
+        assert_eq!((counter.id.as_str(), counter.value, counter.version), ("stock", 5, 1));
+        assert_eq!(counter.description.as_deref(), Some("Stock"));
+        assert_eq!(counter.bounds, bounds);
+        assert!(counter.created_at > 0);
+
+        // Changes within the bounds succeed, including hitting them exactly
+        assert_eq!(db.increment_counter("stock", -5).await?.value, 0);
+        assert_eq!(db.increment_counter("stock", 10).await?.value, 10);
+
+        // Changes past either bound are rejected as constraint violations
+        let err = db.increment_counter("stock", 1).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "counter 'stock' must stay between 0 and 10, but the change would make it 11"
+        );
+        let is_violation = |result: Result<_, DatabaseError>| {
+            matches!(result, Err(DatabaseError::ConstraintViolation { .. }))
+        };
+        assert!(is_violation(db.set_counter("stock", -1).await));
+        let version = db.get_counter("stock").await?.version;
+        assert!(is_violation(db.compare_and_set("stock", version, 11).await));
+        let operations = vec![CounterOperation::Increment { id: "stock".to_string(), amount: -11 }];
+        assert!(matches!(
+            db.apply_batch(&operations).await,
+            Err(DatabaseError::ConstraintViolation { .. })
+        ));
+        assert_eq!(db.get_counter("stock").await?.value, 10);
+
+        // Initial values and bounds are validated on creation
+        assert!(is_violation(db.create_counter("bad", 11, None, bounds, None).await));
+        let inverted = CounterBounds { min_value: Some(1), max_value: Some(0) };
+        assert!(is_violation(db.create_counter("bad", 0, None, inverted, None).await));
+
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_overflow_policy_at_limits() {
+        use OverflowPolicy::*;
+
+        assert_eq!(Error.apply(i64::MAX - 1, 1), Some(i64::MAX));
+        assert_eq!(Error.apply(i64::MAX, 1), None);
+        assert_eq!(Error.apply(i64::MIN, -1), None);
+        assert_eq!(Saturate.apply(i64::MAX, 1), Some(i64::MAX));
+        assert_eq!(Saturate.apply(i64::MIN, i64::MIN), Some(i64::MIN));
+        assert_eq!(Wrap.apply(i64::MAX, 1), Some(i64::MIN));
+        assert_eq!(Wrap.apply(i64::MIN, -1), Some(i64::MAX));
+
+        for policy in [Error, Saturate, Wrap] {
+            assert_eq!(OverflowPolicy::parse(policy.as_str()), Some(policy));
+        }
+    }
 
     #[tokio::test]
-    async fn test_counter_operations() -> Result<()> {
-        // Use an in-memory database for testing
+    async fn test_overflow_policies_on_increment() -> Result<()> {
         let db = Database::connect("sqlite::memory:").await?;
 
-        // Test getting a non-existent counter (should create it and return 0)
-        assert_eq!(db.get_counter("test_counter").await?, 0);
+        // The default policy rejects the increment with a typed error
+        db.set_counter("limit", i64::MAX).await?;
+        let err = db.increment_counter("limit", 1).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::OutOfRange { amount: 1, .. }));
+        assert_eq!(db.get_counter("limit").await?.value, i64::MAX);
 
-        // Test incrementing the counter
-        assert_eq!(db.increment_counter("test_counter", 5).await?, 5);
+        assert!(db.set_overflow_policy("limit", OverflowPolicy::Saturate).await?);
+        assert_eq!(db.increment_counter("limit", 10).await?.value, i64::MAX);
 
-        // Test getting the counter after increment
-        assert_eq!(db.get_counter("test_counter").await?, 5);
+        // Batched increments follow the policy too
+        assert!(db.set_overflow_policy("limit", OverflowPolicy::Wrap).await?);
+        let values = db.increment_counters(&[("limit".to_string(), 2)]).await?;
+        assert_eq!(values[0].value, i64::MIN + 1);
+        let operations = vec![CounterOperation::Increment { id: "limit".to_string(), amount: -2 }];
+        assert_eq!(db.apply_batch(&operations).await?[0].written().unwrap().value, i64::MAX);
 
-        // Test incrementing again
-        assert_eq!(db.increment_counter("test_counter", 3).await?, 8);
+        // Unknown counters have no policy to set
+        assert!(!db.set_overflow_policy("missing", OverflowPolicy::Wrap).await?);
 
-        // Test setting the counter directly
-        db.set_counter("test_counter", 10).await?;
-        assert_eq!(db.get_counter("test_counter").await?, 10);
+        Ok(())
+    }
 
-        // Test listing counters
-        let counters = db.list_counters().await?;
-        assert!(counters.len() >= 1);
-        
-        // The main counter should exist (from ensure_main_counter)
-        assert!(counters.iter().any(|(id, _)| id == MAIN_COUNTER_ID));
-        
-        // Our test counter should exist with value 10
-        assert!(counters.iter().any(|(id, val)| id == "test_counter" && *val == 10));
+    #[tokio::test]
+    async fn test_versions_and_compare_and_set() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
 
-        // Test deleting the counter
-        assert!(db.delete_counter("test_counter").await?);
+        // Version 0 means "must not exist yet"
+        let created = db.compare_and_set("cas", 0, 10).await?;
+        assert_eq!(created, VersionedValue { value: 10, version: 1 });
+        let conflict_version = |result: Result<VersionedValue, DatabaseError>| match result {
+            Err(DatabaseError::Conflict { current_version, .. }) => Some(current_version),
+            _ => None,
+        };
+        assert_eq!(conflict_version(db.compare_and_set("cas", 0, 20).await), Some(1));
+
+        // Every write bumps the version
+        assert_eq!(db.increment_counter("cas", 5).await?, VersionedValue { value: 15, version: 2 });
+        assert_eq!(db.set_counter("cas", 7).await?, VersionedValue { value: 7, version: 3 });
+        assert_eq!(db.get_counter("cas").await?.versioned(), VersionedValue { value: 7, version: 3 });
+
+        // A stale version is rejected and leaves the counter untouched
+        assert_eq!(conflict_version(db.compare_and_set("cas", 2, 100).await), Some(3));
+        assert_eq!(db.get_counter("cas").await?.value, 7);
+
+        // The current version is accepted
+        assert_eq!(
+            db.compare_and_set("cas", 3, 100).await?,
+            VersionedValue { value: 100, version: 4 }
+        );
+
+        // Missing counters report version 0
+        assert_eq!(conflict_version(db.compare_and_set("missing", 1, 1).await), Some(0));
+
+        // A recreated counter carries on from the deleted counter's version,
+        // so versions read before the delete are stale
+        db.delete_counter("cas").await?;
+        assert_eq!(db.set_counter("cas", 1).await?, VersionedValue { value: 1, version: 5 });
+        assert_eq!(conflict_version(db.compare_and_set("cas", 1, 2).await), Some(5));
+        db.delete_counter("cas").await?;
+        assert_eq!(db.increment_counter("cas", 1).await?.version, 6);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_increments() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?
+            .with_idempotency_retention(Duration::from_secs(60));
+
+        let first = db.increment_counter_idempotent("orders", 5, None, None, "request-1").await?;
+        assert_eq!(first, VersionedValue { value: 5, version: 1 });
+
+        // A later increment moves the counter on, but a replay still sees the original result
+        db.increment_counter("orders", 1).await?;
+        let replayed = db.increment_counter_idempotent("orders", 5, None, None, "request-1").await?;
+        assert_eq!(replayed, first);
+        assert_eq!(db.get_counter("orders").await?.value, 6);
+
+        // Reusing a key for a different increment is rejected
+        let err = db.increment_counter_idempotent("orders", 7, None, None, "request-1").await.unwrap_err();
+        assert!(matches!(err, DatabaseError::ConstraintViolation { .. }));
+        let err = db.increment_counter_idempotent("other", 5, None, None, "request-1").await.unwrap_err();
+        assert!(matches!(err, DatabaseError::ConstraintViolation { .. }));
+
+        // Failed increments do not use up their key
+        db.create_counter("capped", 0, None, CounterBounds { min_value: None, max_value: Some(3) }, None).await?;
+        assert!(db.increment_counter_idempotent("capped", 5, None, None, "request-2").await.is_err());
+        db.increment_counter_idempotent("capped", 3, None, None, "request-2").await?;
+        assert_eq!(db.get_counter("capped").await?.value, 3);
+
+        // Once a key is older than the retention window the increment is applied again
+        sqlx::query("UPDATE idempotency_keys SET created_at = created_at - 61000 WHERE key = 'request-1'")
+            .execute(db.pool())
+            .await?;
+        let reapplied = db.increment_counter_idempotent("orders", 5, None, None, "request-1").await?;
+        assert_eq!(reapplied.value, 11);
+
+        // An expired key may even be reused for a different increment; its row is replaced
+        sqlx::query("UPDATE idempotency_keys SET created_at = created_at - 61000 WHERE key = 'request-2'")
+            .execute(db.pool())
+            .await?;
+        assert_eq!(db.increment_counter_idempotent("orders", 1, None, None, "request-2").await?.value, 12);
+        let remaining: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM idempotency_keys")
+            .fetch_one(db.pool())
+            .await?;
+        assert_eq!(remaining, 2);
+
+        // Keys that are never repeated are purged in the background once expired
+        assert_eq!(db.purge_expired_idempotency_keys(now_millis()).await?, 0);
+        assert_eq!(db.purge_expired_idempotency_keys(now_millis() + 61_000).await?, 2);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expired_counters_read_as_absent() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        let expired = Some(now_millis() - 1);
+        let later = Some(now_millis() + 60_000);
+
+        db.create_counter("expired", 7, None, CounterBounds::default(), expired).await?;
+        db.create_counter("live", 1, None, CounterBounds::default(), later).await?;
+        assert!(matches!(db.get_counter("expired").await, Err(DatabaseError::NotFound { .. })));
+        assert!(db.get_counter_stats("expired").await?.is_none());
+        assert_eq!(db.get_counter("live").await?.expires_at, later);
+        let page = db.list_counters_page("", CounterOrder::Id, false, None, 10).await?;
+        assert_eq!(ids_and_values(&page), vec![("live", 1), ("main_counter", 0)]);
+
+        // Writes replace an expired counter instead of building on its value (but not its version)
+        assert_eq!(db.increment_counter("expired", 2).await?, VersionedValue { value: 2, version: 2 });
+        assert_eq!(db.get_counter("expired").await?.expires_at, None);
+        let history = db.get_counter_history("expired", None, None, None, 10).await?;
+        assert_eq!(history[1].reason.as_deref(), Some("expired"));
+
+        // Deleting an expired counter reports that it was already gone
+        db.create_counter("gone", 1, None, CounterBounds::default(), expired).await?;
+        assert!(!db.delete_counter("gone").await?);
+        db.create_counter("gone", 5, None, CounterBounds::default(), None).await?;
+        assert_eq!(db.get_counter("gone").await?.value, 5);
+
+        // Point-in-time reads end at the expiry time, and settings can't revive a counter
+        db.set_counter("stale", 3).await?;
+        let created = db.get_counter_history("stale", None, None, None, 1).await?[0].occurred_at;
+        sqlx::query("UPDATE counters SET expires_at = ? WHERE id = 'stale'")
+            .bind(created + 1)
+            .execute(db.pool())
+            .await?;
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert_eq!(db.get_counter_as_of("stale", created).await?, 3);
+        assert!(matches!(db.get_counter_as_of("stale", created + 1).await, Err(DatabaseError::NotFound { .. })));
+        let mut changes = db.subscribe();
+        assert!(!db.set_overflow_policy("stale", OverflowPolicy::Saturate).await?);
+
+        // Watchers hear about counters deleted because a write found them expired
+        let deleted = CounterChange { id: "stale".to_string(), value: None };
+        assert_eq!(changes.recv().await?, deleted);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_try_acquire() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        let now = 1_700_000_000_000;
+        let allowed = |remaining| RateLimitDecision { allowed: true, remaining, retry_after: 0 };
+
+        // 4 permits per second: a full burst, then one permit every 250ms
+        assert_eq!(db.try_acquire_at("login", 3, 4, 1000, now).await?, allowed(1));
+        assert_eq!(db.try_acquire_at("login", 1, 4, 1000, now).await?, allowed(0));
+        let denied = db.try_acquire_at("login", 2, 4, 1000, now + 100).await?;
+        assert_eq!(denied, RateLimitDecision { allowed: false, remaining: 0, retry_after: 400 });
+        assert_eq!(db.try_acquire_at("login", 1, 4, 1000, now + 250).await?, allowed(0));
+        assert_eq!(db.try_acquire_at("login", 2, 4, 1000, now + 750).await?, allowed(0));
+
+        // Keys are independent, and invalid requests are rejected
+        assert_eq!(db.try_acquire_at("other", 1, 4, 1000, now).await?, allowed(3));
+        assert!(matches!(
+            db.try_acquire_at("login", 5, 4, 1000, now).await,
+            Err(DatabaseError::ConstraintViolation { .. })
+        ));
+
+        // Once replenished, a key's state is purged and it starts over
+        assert_eq!(db.purge_idle_rate_limits(now + 1000).await?, 1);
+        assert_eq!(db.purge_idle_rate_limits(now + 1750).await?, 1);
+        assert_eq!(db.try_acquire_at("login", 4, 4, 1000, now + 1750).await?, allowed(0));
+
+        // Shrinking the limit or window of a busy key never reports negative remaining permits
+        assert_eq!(db.try_acquire_at("shrunk", 10, 10, 1000, now).await?, allowed(0));
+        let denied = db.try_acquire_at("shrunk", 1, 10, 100, now).await?;
+        assert_eq!(denied, RateLimitDecision { allowed: false, remaining: 0, retry_after: 910 });
+        let denied = db.try_acquire_at("shrunk", 1, 2, 1000, now).await?;
+        assert_eq!(denied, RateLimitDecision { allowed: false, remaining: 0, retry_after: 500 });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_quota_period_start() {
+        let leap_day_noon = 1_709_208_000_000; // 2024-02-29T12:00:00Z
+        assert_eq!(QuotaPeriod::Day.period_start(leap_day_noon), 1_709_164_800_000);
+        assert_eq!(QuotaPeriod::Week.period_start(leap_day_noon), 1_708_905_600_000); // Monday 2024-02-26
+        assert_eq!(QuotaPeriod::Month.period_start(leap_day_noon), 1_706_745_600_000); // 2024-02-01
+        assert_eq!(QuotaPeriod::Month.period_start(1_709_251_200_000), 1_709_251_200_000); // 2024-03-01
+        assert_eq!(QuotaPeriod::Week.period_start(1_710_115_199_000), 1_709_510_400_000); // Sunday 2024-03-10
+
+        // Times before the epoch fall in the right periods too
+        assert_eq!(QuotaPeriod::Month.period_start(-3_600_000), -2_678_400_000); // 1969-12-01
+        assert_eq!(QuotaPeriod::Week.period_start(-3_600_000), -259_200_000); // Monday 1969-12-29
+    }
+
+    #[tokio::test]
+    async fn test_quota_counters() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        db.set_counter("api_calls", 8).await?;
+        let quota = CounterQuota { limit: Some(10), period: Some(QuotaPeriod::Month) };
+        assert!(db.set_quota("api_calls", quota).await?);
+        assert!(!db.set_quota("missing", quota).await?);
+        assert_eq!(db.get_counter("api_calls").await?.quota, quota);
+
+        // Increments up to the limit succeed; past it they are rejected and nothing changes
+        db.increment_counter("api_calls", 2).await?;
+        let err = db.increment_counter("api_calls", 1).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::QuotaExceeded { limit: 10, value: 10, amount: 1, .. }));
+        assert_eq!(db.increment_counter("api_calls", -4).await?.value, 6);
+
+        // A wrapping increment can't sneak past the limit by overflowing
+        db.set_counter("wrapping", i64::MAX).await?;
+        db.set_overflow_policy("wrapping", OverflowPolicy::Wrap).await?;
+        db.set_quota("wrapping", CounterQuota { limit: Some(i64::MAX), period: None }).await?;
+        let err = db.increment_counter("wrapping", 1).await.unwrap_err();
+        assert!(matches!(err, DatabaseError::QuotaExceeded { value: i64::MAX, amount: 1, .. }));
+
+        // Nothing is reset while the period lasts; once it is over the counter starts again from zero
+        let now = now_millis();
+        assert!(db.reset_quota_periods(now).await?.is_empty());
+        let next_month = QuotaPeriod::Month.period_start(now) + 32 * 86_400_000;
+        assert_eq!(db.reset_quota_periods(next_month).await?, vec!["api_calls".to_string()]);
+        assert_eq!(db.get_counter("api_calls").await?.value, 0);
+        let history = db.get_counter_history("api_calls", None, None, None, 10).await?;
+        assert_eq!(history.last().unwrap().reason.as_deref(), Some("quota period reset"));
+
+        // Quota periods need a limit and a counter that may be reset to zero
+        let no_limit = CounterQuota { limit: None, period: Some(QuotaPeriod::Day) };
+        assert!(db.set_quota("api_calls", no_limit).await.is_err());
+        db.create_counter("positive", 1, None, CounterBounds { min_value: Some(1), max_value: None }, None).await?;
+        assert!(db.set_quota("positive", quota).await.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_scheduled_resets() -> Result<()> {
+        const DAY: i64 = 86_400_000;
+        let db = Database::connect("sqlite::memory:").await?;
+        db.set_counter("visits", 5).await?;
+        let daily: CronSchedule = "@daily".parse()?;
+        let schedule = db.set_reset_schedule("visits", &daily, 1).await?.unwrap();
+        assert!(db.set_reset_schedule("missing", &daily, 0).await?.is_none());
+        let first_reset = schedule.next_reset_at;
+
+        // Nothing happens before the reset is due
+        assert!(db.apply_due_resets(first_reset - 1).await?.is_empty());
+        db.increment_counter("visits", 3).await?;
+
+        // Four resets were due; they are caught up on with a single reset
+        let restarted_at = first_reset + 3 * DAY + 1;
+        let mut changes = db.subscribe();
+        assert_eq!(db.apply_due_resets(restarted_at).await?, vec!["visits".to_string()]);
+        assert!(db.apply_due_resets(restarted_at).await?.is_empty());
+        assert_eq!(changes.recv().await?.value, Some(1));
+        assert_eq!(db.get_counter("visits").await?.value, 1);
+
+        // Every missed period is archived; those after the first ended at the reset value
+        let periods = db.get_counter_periods("visits", None, 10).await?;
+        let period = |period_start, period_end, final_value| CounterPeriod {
+            period_start,
+            period_end,
+            final_value,
+            reset_at: restarted_at,
+        };
+        assert_eq!(periods, vec![
+            period(schedule.period_start, first_reset, 8),
+            period(first_reset, first_reset + DAY, 1),
+            period(first_reset + DAY, first_reset + 2 * DAY, 1),
+            period(first_reset + 2 * DAY, first_reset + 3 * DAY, 1),
+        ]);
+        assert_eq!(db.get_counter_periods("visits", Some(first_reset + DAY), 10).await?, periods[2..].to_vec());
+
+        let schedule = db.get_reset_schedule("visits").await?.unwrap();
+        assert_eq!((schedule.period_start, schedule.next_reset_at), (first_reset + 3 * DAY, first_reset + 4 * DAY));
+        let history = db.get_counter_history("visits", None, None, None, 10).await?;
+        assert_eq!(history.last().unwrap().reason.as_deref(), Some("scheduled reset"));
+
+        // Reset values must respect the counter's bounds; deleting the counter drops its schedule
+        db.create_counter("bounded", 1, None, CounterBounds { min_value: Some(1), max_value: None }, None).await?;
+        assert!(db.set_reset_schedule("bounded", &daily, 0).await.is_err());
+        db.delete_counter("visits").await?;
+        assert!(db.get_reset_schedule("visits").await?.is_none());
+        assert_eq!(db.get_counter_periods("visits", None, 10).await?.len(), 4);
+
+        Ok(())
+    }
+
+    /// A SQLite database file in the temp directory, deleted along with its
+    /// WAL files when dropped, even if the test fails
+    struct TempDatabaseFile(std::path::PathBuf);
+
+    impl TempDatabaseFile {
+        fn new(name: &str) -> Self {
+            Self(std::env::temp_dir().join(format!("{}_{}.db", name, std::process::id())))
+        }
+
+        fn url(&self) -> String {
+            format!("sqlite:{}", self.0.display())
+        }
+    }
+
+    impl Drop for TempDatabaseFile {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let _ = std::fs::remove_file(format!("{}{}", self.0.display(), suffix));
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_next_ids_are_never_reused() -> Result<()> {
+        let file = TempDatabaseFile::new("next_ids");
+        let url = file.url();
+
+        let db = Database::connect(&url).await?;
+        assert_eq!(db.next_ids("orders", 10).await?.ids(), 1..11);
+        assert_eq!(db.next_ids("orders", 5).await?.ids(), 11..16);
+        assert_eq!(db.next_ids("invoices", 1).await?.ids(), 1..2);
+        assert!(db.next_ids("orders", 0).await.is_err());
+        db.pool().close().await;
+
+        // A restarted server carries on where the sequence left off
+        let db = Database::connect(&url).await?;
+        assert_eq!(db.next_ids("orders", 3).await?.ids(), 16..19);
+
+        // Blocks running past the largest ID are refused
+        sqlx::query("UPDATE sequences SET next_id = ? WHERE name = 'orders'")
+            .bind(i64::MAX - 1)
+            .execute(db.pool())
+            .await?;
+        assert!(db.next_ids("orders", 2).await.is_err());
+        assert_eq!(db.next_ids("orders", 1).await?.first, i64::MAX - 1);
+        db.pool().close().await;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_increment_counters_batch() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        let batch = vec![
+            ("batch_a".to_string(), 1),
+            ("batch_b".to_string(), 10),
+            ("batch_a".to_string(), 2),
+        ];
+        let values: Vec<i64> = db.increment_counters(&batch).await?.iter().map(|v| v.value).collect();
+        assert_eq!(values, vec![1, 10, 3]);
+
+        // A failing increment rolls back the whole batch
+        db.set_counter("batch_max", i64::MAX).await?;
+        let batch = vec![("batch_a".to_string(), 5), ("batch_max".to_string(), 1)];
+        assert!(db.increment_counters(&batch).await.is_err());
+        assert_eq!(db.get_counter("batch_a").await?.value, 3);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_batch_is_atomic() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        db.set_counter("orders", 10).await?;
+        db.set_counter("stale", 1).await?;
+
+        let operations = vec![
+            CounterOperation::Increment { id: "orders".to_string(), amount: 1 },
+            CounterOperation::Increment { id: "orders.eu".to_string(), amount: 1 },
+            CounterOperation::Set { id: "orders.us".to_string(), value: 5 },
+            CounterOperation::Delete { id: "stale".to_string() },
+            CounterOperation::Increment { id: "orders".to_string(), amount: 2 },
+            CounterOperation::Delete { id: "missing".to_string() },
+        ];
+        let results = db.apply_batch(&operations).await?;
+        let values: Vec<Option<i64>> = results.iter().map(|r| r.written().map(|v| v.value)).collect();
+        assert_eq!(values, vec![Some(11), Some(1), Some(5), None, Some(13), None]);
+        assert_eq!((results[3], results[5]), (OperationOutcome::Deleted, OperationOutcome::NotFound));
+        assert!(db.get_counter_stats("stale").await?.is_none());
+
+        // A failing operation rolls back everything before it
+        db.set_counter("orders.max", i64::MAX).await?;
+        let operations = vec![
+            CounterOperation::Increment { id: "orders".to_string(), amount: 1 },
+            CounterOperation::Delete { id: "orders.eu".to_string() },
+            CounterOperation::Increment { id: "orders.max".to_string(), amount: 1 },
+        ];
+        assert!(db.apply_batch(&operations).await.is_err());
+        assert_eq!(db.get_counter("orders").await?.value, 13);
+        assert_eq!(db.get_counter("orders.eu").await?.value, 1);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_receives_changes() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        let mut changes = db.subscribe();
+
+        db.increment_counter("watched", 2).await?;
+        db.set_counter("watched", 10).await?;
+        db.delete_counter("watched").await?;
+
+        // Deleting a missing counter is not a change
+        db.delete_counter("watched").await?;
+
+        for expected in [Some(2), Some(10), None] {
+            let change = changes.recv().await?;
+            assert_eq!(change, CounterChange { id: "watched".to_string(), value: expected });
+        }
+        assert!(changes.try_recv().is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_increment_rate_counts_every_increase() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        let counted = |db: &Database| {
+            let pool = db.pool().clone();
+            async move {
+                sqlx::query_as::<_, (i64, bool)>(
+                    "SELECT total_increments, increment_rate > 0 FROM counters WHERE id = 'raised'"
+                )
+                .fetch_one(&pool)
+                .await
+            }
+        };
+
+        // Creating a counter with a set is not an increment
+        db.set_counter("raised", 5).await?;
+        assert_eq!(counted(&db).await?, (0, false));
+
+        // Sets that raise the value count as increments in both the total and the rate
+        db.set_counter("raised", 8).await?;
+        assert_eq!(counted(&db).await?, (1, true));
+        db.compare_and_set("raised", 2, 9).await?;
+        assert_eq!(counted(&db).await?, (2, true));
+
+        // A wrapping increment lowers the value, so it counts in neither
+        db.set_overflow_policy("raised", OverflowPolicy::Wrap).await?;
+        db.set_counter("raised", i64::MAX).await?;
+        sqlx::query("UPDATE counters SET increment_rate = 0 WHERE id = 'raised'").execute(db.pool()).await?;
+        db.increment_counter("raised", 1).await?;
+        assert_eq!(counted(&db).await?, (3, false));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_counter_stats() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        // Unknown counters have no statistics
+        assert!(db.get_counter_stats("missing").await?.is_none());
+
+        // Existing counters report their value and timestamps
+        db.set_counter("test_counter", 4).await?;
+        let stats = db.get_counter_stats("test_counter").await?.unwrap();
+        assert_eq!(stats.id, "test_counter");
+        assert_eq!(stats.current_value, 4);
+        assert_eq!(stats.description, None);
+        assert!(stats.created_at > 0);
+        assert!(stats.updated_at >= stats.created_at);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counter_history() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        db.create_counter("audited", 10, None, CounterBounds::default(), None).await?;
+        db.increment_counter_with_reason("audited", -3, Some("refund"), None).await?;
+        db.set_counter("audited", 20).await?;
+        assert!(db.increment_counter("audited", i64::MAX).await.is_err());
+        db.delete_counter("audited").await?;
+
+        // Every successful change is recorded in order; the failed one is not
+        let history = db.get_counter_history("audited", None, None, None, 10).await?;
+        let summary: Vec<_> = history.iter().map(|e| (e.kind, e.amount, e.value)).collect();
+        assert_eq!(summary, vec![
+            (CounterEventKind::Set, None, Some(10)),
+            (CounterEventKind::Increment, Some(-3), Some(7)),
+            (CounterEventKind::Set, None, Some(20)),
+            (CounterEventKind::Delete, None, None),
+        ]);
+        assert_eq!(history[1].reason.as_deref(), Some("refund"));
+
+        // Pages resume after the last event returned
+        let page = db.get_counter_history("audited", None, None, Some(history[1].id), 2).await?;
+        assert_eq!(page, history[2..4].to_vec());
+
+        // The time range includes `from` and excludes `to`
+        for (value, occurred_at) in [(1, 1_000), (2, 2_000), (3, 3_000), (4, 4_000)] {
+            insert_event("dated", CounterEventKind::Set, value, occurred_at, &db).await?;
+        }
+        let page = db.get_counter_history("dated", Some(2_000), Some(4_000), None, 10).await?;
+        let values: Vec<_> = page.iter().map(|e| e.value).collect();
+        assert_eq!(values, vec![Some(2), Some(3)]);
+
+        Ok(())
+    }
+
+    /// Appends an event at a chosen time directly to the log, bypassing `Database`
+    async fn insert_event(
+        id: &str,
+        kind: CounterEventKind,
+        value: i64,
+        occurred_at: i64,
+        db: &Database,
+    ) -> Result<()> {
+        let value = (kind != CounterEventKind::Delete).then_some(value);
+        sqlx::query("INSERT INTO counter_events (counter_id, kind, value, occurred_at) VALUES (?, ?, ?, ?)")
+            .bind(id)
+            .bind(kind)
+            .bind(value)
+            .bind(occurred_at)
+            .execute(db.pool())
+            .await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_counter_as_of() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        insert_event("ledger", CounterEventKind::Set, 10, 1_000, &db).await?;
+        insert_event("ledger", CounterEventKind::Increment, 15, 2_000, &db).await?;
+        insert_event("ledger", CounterEventKind::Delete, 0, 3_000, &db).await?;
+        insert_event("ledger", CounterEventKind::Set, 7, 4_000, &db).await?;
+
+        let as_of = |t| db.get_counter_as_of("ledger", t);
+        assert!(matches!(as_of(999).await, Err(DatabaseError::NotFound { .. })));
+        assert_eq!(as_of(1_000).await?, 10);
+        assert_eq!(as_of(2_999).await?, 15);
+        assert!(matches!(as_of(3_500).await, Err(DatabaseError::NotFound { .. })));
+        assert_eq!(as_of(i64::MAX).await?, 7);
+
+        // The log cannot be rewritten
+        let rewrite = sqlx::query("UPDATE counter_events SET value = 0").execute(db.pool()).await;
+        assert!(rewrite.is_err());
+        let erase = sqlx::query("DELETE FROM counter_events").execute(db.pool()).await;
+        assert!(erase.is_err());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints_bound_replay() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        for _ in 0..(2 * CHECKPOINT_INTERVAL + 5) {
+            db.increment_counter("busy", 1).await?;
+        }
+
+        let checkpoints: Vec<(i64, i64)> =
+            sqlx::query_as("SELECT event_id, value FROM counter_checkpoints WHERE counter_id = 'busy' ORDER BY event_id")
+                .fetch_all(db.pool())
+                .await?;
+        let values: Vec<_> = checkpoints.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, vec![CHECKPOINT_INTERVAL, 2 * CHECKPOINT_INTERVAL]);
+
+        // Reading the present replays the events after the last checkpoint
+        assert_eq!(db.get_counter_as_of("busy", i64::MAX).await?, 2 * CHECKPOINT_INTERVAL + 5);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_recreated_main_counter_has_history() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+        db.delete_counter(MAIN_COUNTER_ID).await?;
+        assert!(db.get_counter_as_of(MAIN_COUNTER_ID, i64::MAX).await.is_err());
+
+        // As on the next start of the server
+        db.ensure_main_counter().await?;
+        assert_eq!(db.get_counter_as_of(MAIN_COUNTER_ID, i64::MAX).await?, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counter_series() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        db.increment_counter("hits", 2).await?;
+        db.increment_counter("hits", 3).await?;
+        db.set_overflow_policy("hits", OverflowPolicy::Saturate).await?;
+        db.increment_counter("hits", i64::MAX).await?;
+        db.increment_counter("hits", i64::MAX).await?;
+
+        // Sum every bucket around now, so the test can't straddle a boundary
+        let now = db.get_counter_history("hits", None, None, None, 1).await?[0].occurred_at;
+        for granularity in Granularity::ALL {
+            let series = db.get_counter_series("hits", granularity, now - 86_400_000, now + 86_400_000).await?;
+            let increments: i64 = series.iter().map(|b| b.increments).sum();
+            assert_eq!(increments, 4);
+            assert!(series.iter().any(|b| b.total == i64::MAX), "bucket totals saturate");
+        }
+
+        // Buckets without increments are zero-filled
+        sqlx::query(
+            "INSERT INTO counter_rollups (counter_id, granularity, bucket_start, total, increments)
+             VALUES ('sparse', 'minute', 60000, 7, 2)"
+        )
+        .execute(db.pool())
+        .await?;
+        let series = db.get_counter_series("sparse", Granularity::Minute, 30_000, 180_000).await?;
+        assert_eq!(series, vec![
+            SeriesBucket { bucket_start: 0, total: 0, increments: 0 },
+            SeriesBucket { bucket_start: 60_000, total: 7, increments: 2 },
+            SeriesBucket { bucket_start: 120_000, total: 0, increments: 0 },
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_increment_rate_decays() {
+        let minute = 60_000;
+        assert_eq!(decayed_rate(3.0, None, minute), 0.0);
+        assert_eq!(decayed_rate(3.0, Some(minute), minute), 3.0);
+
+        // After one time constant the rate has dropped to 1/e
+        let constant = (RATE_TIME_CONSTANT_MINUTES * minute as f64) as i64;
+        let decayed = decayed_rate(3.0, Some(0), constant);
+        assert!((decayed - 3.0 / std::f64::consts::E).abs() < 1e-9);
+
+        // Steady increments converge on their actual rate
+        let mut rate = 0.0;
+        for i in 0..1000 {
+            rate = decayed_rate(rate, Some((i - 1) * minute / 4), i * minute / 4) + 1.0 / RATE_TIME_CONSTANT_MINUTES;
+        }
+        assert!((rate - 4.0).abs() < 0.2, "rate was {}", rate);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        assert_eq!(HistogramBucket::index_of(0), None);
+        assert_eq!(HistogramBucket::index_of(1), Some(1));
+        assert_eq!(HistogramBucket::index_of(7), Some(3));
+        assert_eq!(HistogramBucket::index_of(8), Some(4));
+        assert_eq!(HistogramBucket::index_of(i64::MAX), Some(63));
+        let top = HistogramBucket { bucket: 63, count: 1 };
+        assert_eq!((top.lower_bound(), top.upper_bound()), (1 << 62, i64::MAX));
+
+        assert_eq!(IncrementHistogram::default().percentile(50.0), None);
+
+        // 90 increments of 1 and 10 increments between 64 and 127
+        let histogram = IncrementHistogram {
+            buckets: vec![HistogramBucket { bucket: 1, count: 90 }, HistogramBucket { bucket: 7, count: 10 }],
+        };
+        assert_eq!(histogram.percentile(50.0), Some(1));
+        assert_eq!(histogram.percentile(90.0), Some(1));
+        let p99 = histogram.percentile(99.0).unwrap();
+        assert!((64..=127).contains(&p99));
+        assert_eq!(histogram.percentile(100.0), Some(127));
+    }
+
+    #[tokio::test]
+    async fn test_increment_histogram() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        for amount in [1, 1, 3, 5, 6, -4] {
+            db.increment_counter("amounts", amount).await?;
+        }
+        db.set_counter("amounts", 100).await?;
+
+        // Only positive increments are counted, sets and decrements are not
+        let stats = db.get_counter_stats("amounts").await?.unwrap();
+        assert_eq!(stats.increment_histogram.buckets, vec![
+            HistogramBucket { bucket: 1, count: 2 },
+            HistogramBucket { bucket: 2, count: 1 },
+            HistogramBucket { bucket: 3, count: 2 },
+        ]);
+        assert_eq!(stats.increment_histogram.percentile(40.0), Some(1));
+
+        // Deleting the counter discards its histogram
+        db.delete_counter("amounts").await?;
+        db.increment_counter("amounts", 2).await?;
+        let stats = db.get_counter_stats("amounts").await?.unwrap();
+        assert_eq!(stats.increment_histogram.buckets, vec![HistogramBucket { bucket: 2, count: 1 }]);
+
+        Ok(())
+    }
+
+    /// Reduces a page of counters to (id, value) pairs for easy comparison
+    fn ids_and_values(page: &[Counter]) -> Vec<(&str, i64)> {
+        page.iter().map(|c| (c.id.as_str(), c.value)).collect()
+    }
+
+    #[tokio::test]
+    async fn test_list_counters_page() -> Result<()> {
+        let db = Database::connect("sqlite::memory:").await?;
+
+        for (id, value) in [("orders", 7), ("orders.eu", 3), ("orders.us", 3), ("orders_x", 1), ("users", 9)] {
+            db.set_counter(id, value).await?;
+        }
+
+        // Walk the "orders" prefix by ID, two at a time
+        let page = db.list_counters_page("orders", CounterOrder::Id, false, None, 2).await?;
+        assert_eq!(ids_and_values(&page), vec![("orders", 7), ("orders.eu", 3)]);
+
+        let cursor = CounterCursor { id: "orders.eu".to_string(), value: 3 };
+        let page = db.list_counters_page("orders", CounterOrder::Id, false, Some(&cursor), 2).await?;
+        assert_eq!(ids_and_values(&page), vec![("orders.us", 3), ("orders_x", 1)]);
+
+        // "_" in the prefix is matched literally, not as a wildcard
+        let page = db.list_counters_page("orders_", CounterOrder::Id, false, None, 10).await?;
+        assert_eq!(ids_and_values(&page), vec![("orders_x", 1)]);
+
+        // Ordering by value descending breaks ties by ID across page boundaries
+        let page = db.list_counters_page("orders", CounterOrder::Value, true, None, 2).await?;
+        assert_eq!(ids_and_values(&page), vec![("orders", 7), ("orders.us", 3)]);
 
-        // After deletion, getting it should create a new one with value 0
-        assert_eq!(db.get_counter("test_counter").await?, 0);
+        let cursor = CounterCursor { id: "orders.us".to_string(), value: 3 };
+        let page = db.list_counters_page("orders", CounterOrder::Value, true, Some(&cursor), 2).await?;
+        assert_eq!(ids_and_values(&page), vec![("orders.eu", 3), ("orders_x", 1)]);
 
         Ok(())
     }
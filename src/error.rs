@@ -0,0 +1,225 @@
+//! Error types for the database layer.
+//!
+//! This module provides:
+//! - `DatabaseError`, the error returned by every `Database` method
+//! - Classification of SQLite errors into retryable and permanent failures
+//! - The conversion from `DatabaseError` to the `tonic::Status` sent to clients,
+//!   including the standard `google.rpc.QuotaFailure` detail for exhausted quotas
+
+use prost::Message;
+use tonic::{Code, Status};
+
+/// Convenience alias for results returned by the database layer
+pub type Result<T, E = DatabaseError> = std::result::Result<T, E>;
+
+/// Errors returned by `Database` operations
+///
+/// The variants tell callers whether a failure is worth retrying (`Busy`,
+/// `Conflict`) or permanent. SQL error details are kept in the `source`
+/// chain for logging and never appear in the error message itself.
+#[derive(Debug, thiserror::Error)]
+pub enum DatabaseError {
+    /// The counter does not exist
+    #[error("counter '{id}' not found")]
+    NotFound { id: String },
+
+    /// A counter with this ID already exists
+    #[error("counter '{id}' already exists")]
+    AlreadyExists { id: String },
+
+    /// The counter's version differs from the one the caller expected
+    #[error("counter '{id}' is at version {current_version}, expected {expected_version}")]
+    Conflict {
+        id: String,
+        expected_version: i64,
+        current_version: i64,
+    },
+
+    /// The database is busy or locked by another writer; retrying may succeed
+    #[error("database is busy, please retry")]
+    Busy(#[source] sqlx::Error),
+
+    /// The change would break a constraint on the counter, such as its bounds
+    #[error("{message}")]
+    ConstraintViolation { message: String },
+
+    /// The change would overflow the counter under its overflow policy
+    #[error("incrementing counter '{id}' by {amount} would overflow")]
+    OutOfRange { id: String, amount: i64 },
+
+    /// The increment would take the counter past its quota
+    #[error("incrementing counter '{id}' by {amount} would exceed its quota of {limit} (currently {value})")]
+    QuotaExceeded { id: String, limit: i64, value: i64, amount: i64 },
+
+    /// Any other failure, e.g. an I/O error or a corrupt database
+    #[error("internal database error")]
+    Internal(#[source] Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// `google.rpc.Status`, the payload of the `grpc-status-details-bin` trailer
+#[derive(Clone, PartialEq, Message)]
+pub struct RpcStatus {
+    /// The status code
+    #[prost(int32, tag = "1")]
+    pub code: i32,
+    /// The error message
+    #[prost(string, tag = "2")]
+    pub message: String,
+    /// Details about the error, each packed into a `google.protobuf.Any`
+    #[prost(message, repeated, tag = "3")]
+    pub details: Vec<prost_types::Any>,
+}
+
+/// `google.rpc.QuotaFailure`, describing which quotas a request exhausted
+#[derive(Clone, PartialEq, Message)]
+pub struct QuotaFailure {
+    /// One violation per exhausted quota
+    #[prost(message, repeated, tag = "1")]
+    pub violations: Vec<QuotaViolation>,
+}
+
+/// `google.rpc.QuotaFailure.Violation`
+#[derive(Clone, PartialEq, Message)]
+pub struct QuotaViolation {
+    /// The subject of the quota, e.g. "counter:api_calls"
+    #[prost(string, tag = "1")]
+    pub subject: String,
+    /// Why the quota check failed
+    #[prost(string, tag = "2")]
+    pub description: String,
+}
+
+/// Type URL of `QuotaFailure` when packed into a `google.protobuf.Any`
+pub const QUOTA_FAILURE_TYPE_URL: &str = "type.googleapis.com/google.rpc.QuotaFailure";
+
+/// Builds a `RESOURCE_EXHAUSTED` status carrying a `QuotaFailure` detail
+fn quota_exhausted(subject: String, message: String) -> Status {
+    let failure = QuotaFailure {
+        violations: vec![QuotaViolation { subject, description: message.clone() }],
+    };
+    let details = RpcStatus {
+        code: Code::ResourceExhausted as i32,
+        message: message.clone(),
+        details: vec![prost_types::Any {
+            type_url: QUOTA_FAILURE_TYPE_URL.to_string(),
+            value: failure.encode_to_vec(),
+        }],
+    };
+    Status::with_details(Code::ResourceExhausted, message, details.encode_to_vec().into())
+}
+
+impl DatabaseError {
+    /// Creates an `Internal` error from a message
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::Internal(message.into().into())
+    }
+
+    /// Returns true if the operation may succeed when retried unchanged
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, Self::Busy(_))
+    }
+}
+
+impl From<sqlx::Error> for DatabaseError {
+    fn from(e: sqlx::Error) -> Self {
+        match &e {
+            sqlx::Error::PoolTimedOut => Self::Busy(e),
+            sqlx::Error::Database(db_error) => {
+                // Extended result codes keep the primary code in the low byte
+                let primary_code = db_error
+                    .code()
+                    .and_then(|code| code.parse::<i32>().ok())
+                    .map(|code| code & 0xff);
+
+                match (primary_code, db_error.kind()) {
+                    // SQLITE_BUSY and SQLITE_LOCKED
+                    (Some(5 | 6), _) => Self::Busy(e),
+                    (_, sqlx::error::ErrorKind::Other) => Self::Internal(e.into()),
+                    _ => Self::ConstraintViolation {
+                        message: "the change violates a database constraint".to_string(),
+                    },
+                }
+            }
+            _ => Self::Internal(e.into()),
+        }
+    }
+}
+
+impl From<sqlx::migrate::MigrateError> for DatabaseError {
+    fn from(e: sqlx::migrate::MigrateError) -> Self {
+        Self::Internal(e.into())
+    }
+}
+
+impl From<DatabaseError> for Status {
+    fn from(e: DatabaseError) -> Self {
+        match &e {
+            DatabaseError::NotFound { .. } => Status::not_found(e.to_string()),
+            DatabaseError::AlreadyExists { .. } => Status::already_exists(e.to_string()),
+            DatabaseError::Conflict { .. } => Status::aborted(e.to_string()),
+            DatabaseError::Busy(_) => Status::unavailable(e.to_string()),
+            DatabaseError::ConstraintViolation { .. } => Status::failed_precondition(e.to_string()),
+            DatabaseError::OutOfRange { .. } => Status::out_of_range(e.to_string()),
+            DatabaseError::QuotaExceeded { id, .. } => quota_exhausted(format!("counter:{}", id), e.to_string()),
+            DatabaseError::Internal(source) => {
+                // Details stay in the server log; clients only see the category
+                eprintln!("Database error: {:?}", source);
+                Status::internal(e.to_string())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_codes() {
+        let not_found = DatabaseError::NotFound { id: "x".to_string() };
+        assert_eq!(Status::from(not_found).code(), Code::NotFound);
+
+        let conflict = DatabaseError::Conflict { id: "x".to_string(), expected_version: 1, current_version: 2 };
+        assert_eq!(Status::from(conflict).code(), Code::Aborted);
+
+        let busy = DatabaseError::Busy(sqlx::Error::PoolTimedOut);
+        assert!(busy.is_retryable());
+        assert_eq!(Status::from(busy).code(), Code::Unavailable);
+
+        let overflow = DatabaseError::OutOfRange { id: "x".to_string(), amount: 1 };
+        assert!(!overflow.is_retryable());
+        assert_eq!(Status::from(overflow).code(), Code::OutOfRange);
+
+        // Exhausted quotas carry a QuotaFailure naming the counter
+        let quota = DatabaseError::QuotaExceeded { id: "x".to_string(), limit: 10, value: 10, amount: 1 };
+        let status = Status::from(quota);
+        assert_eq!(status.code(), Code::ResourceExhausted);
+        let details = RpcStatus::decode(status.details()).unwrap();
+        assert_eq!(details.code, Code::ResourceExhausted as i32);
+        assert_eq!(details.details[0].type_url, QUOTA_FAILURE_TYPE_URL);
+        let failure = QuotaFailure::decode(details.details[0].value.as_slice()).unwrap();
+        assert_eq!(failure.violations[0].subject, "counter:x");
+        assert_eq!(failure.violations[0].description, status.message());
+
+        // Internal details never reach the client
+        let internal = DatabaseError::from(sqlx::Error::Protocol("near \"SELEC\": syntax error".into()));
+        let status = Status::from(internal);
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "internal database error");
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_errors_are_classified() {
+        let pool = sqlx::SqlitePool::connect("sqlite::memory:").await.unwrap();
+        sqlx::query("CREATE TABLE t (id TEXT PRIMARY KEY, n INTEGER CHECK (n >= 0))")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let err = sqlx::query("INSERT INTO t VALUES ('a', -1)").execute(&pool).await.unwrap_err();
+        assert!(matches!(DatabaseError::from(err), DatabaseError::ConstraintViolation { .. }));
+
+        let err = sqlx::query("SELECT * FROM missing").execute(&pool).await.unwrap_err();
+        assert!(matches!(DatabaseError::from(err), DatabaseError::Internal(_)));
+    }
+}
@@ -0,0 +1,69 @@
+//! Client-side allocation of unique IDs from server-side sequences.
+
+use std::ops::Range;
+
+use tonic::transport::Channel;
+use tonic::{Request, Status};
+
+use crate::hello_service::hello_service_client::HelloServiceClient;
+use crate::hello_service::NextIdsRequest;
+
+/// Hands out unique IDs from a server-side sequence, leasing them in blocks
+///
+/// Each lease reserves `block_size` consecutive IDs with a single NextIds
+/// call, and IDs are then handed out locally until the block runs out. The
+/// server never hands out an ID twice, so IDs left in a block when the
+/// allocator is dropped are simply skipped, even across restarts.
+pub struct IdAllocator {
+    /// Client used to lease blocks
+    client: HelloServiceClient<Channel>,
+    /// Name of the sequence IDs are taken from
+    sequence: String,
+    /// Number of IDs leased at a time
+    block_size: i64,
+    /// IDs of the current block that have not been handed out yet
+    leased: Range<i64>,
+}
+
+impl IdAllocator {
+    /// Creates an allocator taking IDs from `sequence`, `block_size` at a time
+    ///
+    /// # Returns
+    ///
+    /// The allocator, or `INVALID_ARGUMENT` if `block_size` is not positive
+    #[allow(clippy::result_large_err)]
+    pub fn new(
+        client: HelloServiceClient<Channel>,
+        sequence: impl Into<String>,
+        block_size: i64,
+    ) -> Result<Self, Status> {
+        if block_size <= 0 {
+            return Err(Status::invalid_argument(format!(
+                "block_size must be positive, got {}",
+                block_size
+            )));
+        }
+
+        Ok(Self {
+            client,
+            sequence: sequence.into(),
+            block_size,
+            leased: 0..0,
+        })
+    }
+
+    /// Returns the next ID, leasing a new block from the server if the current one is used up
+    pub async fn next_id(&mut self) -> Result<i64, Status> {
+        if self.leased.is_empty() {
+            let request = Request::new(NextIdsRequest {
+                sequence: self.sequence.clone(),
+                count: self.block_size,
+            });
+            let block = self.client.next_ids(request).await?.into_inner();
+            self.leased = block.first_id..block.first_id.saturating_add(block.count);
+        }
+        self.leased
+            .next()
+            .ok_or_else(|| Status::internal("the server leased an empty block of IDs"))
+    }
+}
@@ -0,0 +1,20 @@
+//! # HelloService library
+//!
+//! The generated protobuf code for the HelloService gRPC server, the modules
+//! the server is built from, and client helpers that other programs can build
+//! on.
+
+pub mod convert;
+pub mod database;
+pub mod error;
+pub mod id_allocator;
+pub mod pagination;
+pub mod reaper;
+pub mod schedule;
+pub mod scheduler;
+pub mod tdd_sample;
+
+// Import the generated protobuf code
+pub mod hello_service {
+    tonic::include_proto!("hello_service");
+}
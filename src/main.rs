@@ -2,35 +2,325 @@
 //! 
 //! A gRPC server implementation using Tonic and SQLite that provides:
 //! - SayHello: Basic greeting service
-//! - IncrementCounter: Increments a counter stored in SQLite
-//! - GetCounter: Retrieves the current counter value from SQLite
-//! - GetCounterStats: Retrieves statistics about the counter
+//! - IncrementCounter: Increments a named counter stored in SQLite
+//! - GetCounter: Retrieves the current value of a named counter from SQLite
+//! - ListCounters: Lists counters page by page, filtered by ID prefix
+//! - GetCounterStats: Retrieves statistics about a counter
+//! - WatchCounter: Streams live updates of a counter
+//! - StreamIncrements: Applies a stream of increments in batched transactions
+//! - CompareAndSet: Sets a counter only if its version is unchanged
+//! - ApplyBatch: Applies several counter operations in one transaction
+//! - SetOverflowPolicy: Chooses how a counter handles arithmetic overflow
+//! - SetQuota: Sets a counter's quota and how often it resets
+//! - CreateCounter: Creates a counter with optional min/max bounds
+//! - DecrementCounter: Decrements a counter, respecting its lower bound
+//! - GetCounterHistory: Lists the recorded changes of a counter
+//! - GetCounterSeries: Returns a counter's increment totals per time bucket
+//! - SetResetSchedule: Sets the cron-like schedule on which a counter is reset
+//! - GetCounterPeriods: Lists the final values of a counter's reset periods
+//! - NextIds: Reserves a block of unique IDs from a named sequence
+//!
+//! Counters may expire; a background `Reaper` deletes expired counters, along
+//! with idle rate limit state and expired idempotency keys.
+//! A `ResetScheduler` applies reset schedules, catching up on resets missed
+//! while the server was down, and starts new quota periods.
+//!
+//! A second service, RateLimitService, rate limits arbitrary keys with its
+//! state stored in the same SQLite database:
+//! - TryAcquire: Acquires permits from a key's rate limit
 
+use std::collections::BTreeMap;
 use std::sync::Arc;
 use std::net::SocketAddr;
 use anyhow::Result;
-use tonic::{transport::Server, Request, Response, Status};
+use tokio::sync::{broadcast::{self, error::RecvError}, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Server, Request, Response, Status, Streaming};
 
-// Import our modules
-pub mod tdd_sample;
-pub mod database;
-
-// Import the database module types
-use database::{Database, MAIN_COUNTER_ID};
-
-// Import the generated protobuf code
-pub mod hello_service {
-    tonic::include_proto!("hello_service");
-}
+// Import the library modules
+use agentic_protos::database::{now_millis, CounterBounds, CounterChange, CounterCursor, CounterQuota, Database};
+use agentic_protos::error::DatabaseError;
+use agentic_protos::reaper::{Reaper, DEFAULT_REAP_INTERVAL};
+use agentic_protos::pagination;
+use agentic_protos::schedule::{self, CronSchedule};
+use agentic_protos::scheduler::{ResetScheduler, DEFAULT_SCHEDULER_TICK};
+use agentic_protos::convert::{
+    clamp_int32, counter_operation, counter_order, duration_millis, granularity, millis_timestamp, operation_result,
+    overflow_policy, quota_period, resolve_counter_id, timestamp_millis, watch_update, widened,
+};
 
 // Import the gRPC service and message types
-use hello_service::{
+use agentic_protos::hello_service::{
+    self,
     hello_service_server::{HelloService, HelloServiceServer},
     HelloRequest, HelloResponse,
     IncrementCounterRequest, IncrementCounterResponse,
     GetCounterRequest, GetCounterResponse,
+    ListCountersRequest, ListCountersResponse, CounterEntry,
+    GetCounterStatsRequest, GetCounterStatsResponse,
+    WatchCounterRequest, WatchCounterResponse,
+    StreamIncrementsRequest, StreamIncrementsResponse,
+    CompareAndSetRequest, CompareAndSetResponse,
+    ApplyBatchRequest, ApplyBatchResponse,
+    SetOverflowPolicyRequest, SetOverflowPolicyResponse,
+    SetQuotaRequest, SetQuotaResponse,
+    CreateCounterRequest, CreateCounterResponse,
+    DecrementCounterRequest, DecrementCounterResponse,
+    GetCounterHistoryRequest, GetCounterHistoryResponse,
+    GetCounterSeriesRequest, GetCounterSeriesResponse,
+    SetResetScheduleRequest, SetResetScheduleResponse,
+    GetCounterPeriodsRequest, GetCounterPeriodsResponse,
+    NextIdsRequest, NextIdsResponse,
+    rate_limit_service_server::{RateLimitService, RateLimitServiceServer},
+    TryAcquireRequest, TryAcquireResponse,
 };
 
+/// Number of updates buffered between a watcher task and its gRPC stream
+const WATCH_STREAM_BUFFER: usize = 16;
+
+/// Maximum number of streamed increments committed in one transaction
+const INCREMENT_BATCH_SIZE: usize = 500;
+
+/// Metadata key carrying the number of increments committed by a failed StreamIncrements call
+const APPLIED_INCREMENTS_METADATA: &str = "applied-increments";
+
+/// Maximum number of operations accepted in one ApplyBatch request
+const MAX_BATCH_OPERATIONS: usize = 1000;
+
+/// Maximum number of buckets returned by one GetCounterSeries request
+const MAX_SERIES_BUCKETS: i64 = 10_000;
+
+/// Longest rate limit window accepted by TryAcquire, in milliseconds (366 days)
+const MAX_RATE_LIMIT_WINDOW_MILLIS: i64 = 366 * 24 * 60 * 60 * 1000;
+
+/// Maximum number of IDs reserved by one NextIds request
+const MAX_ID_BLOCK: i64 = 1_000_000;
+
+/// Maximum length of an idempotency key accepted over gRPC
+pub const MAX_IDEMPOTENCY_KEY_LEN: usize = 256;
+
+/// Environment variable overriding how many seconds idempotency keys are remembered
+const IDEMPOTENCY_RETENTION_ENV: &str = "IDEMPOTENCY_RETENTION_SECS";
+
+/// Environment variable overriding how many seconds pass between purges of expired state
+const REAP_INTERVAL_ENV: &str = "EXPIRY_REAP_INTERVAL_SECS";
+
+/// Validates the idempotency key of an IncrementCounter request
+///
+/// Keys must be non-empty and at most `MAX_IDEMPOTENCY_KEY_LEN` bytes long.
+#[allow(clippy::result_large_err)]
+fn validate_idempotency_key(key: &str) -> Result<(), Status> {
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LEN {
+        return Err(Status::invalid_argument(format!(
+            "idempotency_key must be between 1 and {} bytes long",
+            MAX_IDEMPOTENCY_KEY_LEN
+        )));
+    }
+    Ok(())
+}
+
+/// Converts the `expires_at` field of a request into milliseconds since the Unix epoch
+///
+/// # Returns
+///
+/// The expiry time, `None` if it is unset, or an `INVALID_ARGUMENT` status if
+/// it is not in the future
+#[allow(clippy::result_large_err)]
+fn expiry_millis(expires_at: Option<&prost_types::Timestamp>) -> Result<Option<i64>, Status> {
+    let Some(expires_at) = expires_at else {
+        return Ok(None);
+    };
+    let expires_at = timestamp_millis(expires_at)?;
+    if expires_at <= now_millis() {
+        return Err(Status::invalid_argument("expires_at must be in the future"));
+    }
+    Ok(Some(expires_at))
+}
+
+/// Validates a counter ID prefix used to filter listings
+///
+/// The prefix may be empty, otherwise it follows the same rules as a counter ID.
+#[allow(clippy::result_large_err)]
+fn validate_prefix(prefix: &str) -> Result<(), Status> {
+    if prefix.is_empty() {
+        return Ok(());
+    }
+    resolve_counter_id(prefix).map(|_| ())
+}
+
+/// Encodes the page token resuming after `cursor` for a ListCounters request
+fn encode_list_token(request: &ListCountersRequest, cursor: &CounterCursor) -> String {
+    pagination::encode_page_token(&[
+        &request.order_by.to_string(),
+        &request.descending.to_string(),
+        &request.prefix,
+        &cursor.value.to_string(),
+        &cursor.id,
+    ])
+}
+
+/// Decodes a ListCounters page token back into a cursor
+///
+/// Tokens are only valid for the prefix and ordering they were issued for.
+#[allow(clippy::result_large_err)]
+fn decode_list_token(request: &ListCountersRequest) -> Result<Option<CounterCursor>, Status> {
+    if request.page_token.is_empty() {
+        return Ok(None);
+    }
+
+    let invalid = || Status::invalid_argument("invalid page_token");
+    let fields = pagination::decode_page_token(&request.page_token).ok_or_else(invalid)?;
+    let [order_by, descending, prefix, value, id] = fields.as_slice() else {
+        return Err(invalid());
+    };
+
+    if *order_by != request.order_by.to_string()
+        || *descending != request.descending.to_string()
+        || *prefix != request.prefix
+    {
+        return Err(Status::invalid_argument(
+            "page_token was issued for a different prefix or sort order",
+        ));
+    }
+
+    Ok(Some(CounterCursor {
+        id: id.clone(),
+        value: value.parse().map_err(|_| invalid())?,
+    }))
+}
+
+/// Time range of a GetCounterHistory request in milliseconds since the Unix epoch
+struct HistoryRange {
+    /// Inclusive lower limit, if any
+    from: Option<i64>,
+    /// Exclusive upper limit, if any
+    to: Option<i64>,
+}
+
+impl HistoryRange {
+    /// Reads the time range of a GetCounterHistory request
+    #[allow(clippy::result_large_err)]
+    fn of(request: &GetCounterHistoryRequest) -> Result<Self, Status> {
+        let range = HistoryRange {
+            from: request.start_time.as_ref().map(timestamp_millis).transpose()?,
+            to: request.end_time.as_ref().map(timestamp_millis).transpose()?,
+        };
+        if let (Some(from), Some(to)) = (range.from, range.to) {
+            if from > to {
+                return Err(Status::invalid_argument("start_time must not be after end_time"));
+            }
+        }
+        Ok(range)
+    }
+
+    /// Formats the range for use in a page token
+    fn token_fields(&self) -> [String; 2] {
+        let field = |limit: Option<i64>| limit.map(|millis| millis.to_string()).unwrap_or_default();
+        [field(self.from), field(self.to)]
+    }
+}
+
+/// Encodes the page token resuming after event `last_event_id` for a GetCounterHistory request
+fn encode_history_token(counter_id: &str, range: &HistoryRange, last_event_id: i64) -> String {
+    let [from, to] = range.token_fields();
+    pagination::encode_page_token(&[counter_id, &from, &to, &last_event_id.to_string()])
+}
+
+/// Decodes a GetCounterHistory page token back into the ID of the last event returned
+///
+/// Tokens are only valid for the counter and time range they were issued for.
+#[allow(clippy::result_large_err)]
+fn decode_history_token(
+    page_token: &str,
+    counter_id: &str,
+    range: &HistoryRange,
+) -> Result<Option<i64>, Status> {
+    if page_token.is_empty() {
+        return Ok(None);
+    }
+
+    let invalid = || Status::invalid_argument("invalid page_token");
+    let fields = pagination::decode_page_token(page_token).ok_or_else(invalid)?;
+    let [token_counter_id, from, to, last_event_id] = fields.as_slice() else {
+        return Err(invalid());
+    };
+
+    if token_counter_id != counter_id || [from.clone(), to.clone()] != range.token_fields() {
+        return Err(Status::invalid_argument(
+            "page_token was issued for a different counter or time range",
+        ));
+    }
+
+    last_event_id.parse().map(Some).map_err(|_| invalid())
+}
+
+/// Encodes the page token resuming after the period ending at `last_period_end` for a GetCounterPeriods request
+fn encode_periods_token(counter_id: &str, last_period_end: i64) -> String {
+    pagination::encode_page_token(&[counter_id, &last_period_end.to_string()])
+}
+
+/// Decodes a GetCounterPeriods page token back into the end of the last period returned
+///
+/// Tokens are only valid for the counter they were issued for.
+#[allow(clippy::result_large_err)]
+fn decode_periods_token(page_token: &str, counter_id: &str) -> Result<Option<i64>, Status> {
+    if page_token.is_empty() {
+        return Ok(None);
+    }
+
+    let invalid = || Status::invalid_argument("invalid page_token");
+    let fields = pagination::decode_page_token(page_token).ok_or_else(invalid)?;
+    let [token_counter_id, last_period_end] = fields.as_slice() else {
+        return Err(invalid());
+    };
+
+    if token_counter_id != counter_id {
+        return Err(Status::invalid_argument("page_token was issued for a different counter"));
+    }
+
+    last_period_end.parse().map(Some).map_err(|_| invalid())
+}
+
+/// Forwards changes of one counter from the database to a WatchCounter stream
+///
+/// Runs until the client disconnects, which is noticed right away even if
+/// the counter never changes again. If the stream falls behind and the
+/// broadcast channel drops changes, the skipped changes are replaced by a
+/// single update carrying the counter's latest value.
+async fn forward_counter_changes(
+    db: Arc<Database>,
+    counter_id: String,
+    mut changes: broadcast::Receiver<CounterChange>,
+    tx: mpsc::Sender<Result<WatchCounterResponse, Status>>,
+) {
+    loop {
+        let received = tokio::select! {
+            received = changes.recv() => received,
+            // The client went away
+            _ = tx.closed() => break,
+        };
+        let update = match received {
+            Ok(change) if change.id == counter_id => Ok(watch_update(&counter_id, change.value)),
+            Ok(_) => continue,
+            Err(RecvError::Lagged(skipped)) => {
+                println!("Watcher of '{}' lagged, skipped {} changes", counter_id, skipped);
+                // Read the counter as GetCounter does, so expired counters show as deleted
+                match db.get_counter(&counter_id).await {
+                    Ok(counter) => Ok(watch_update(&counter_id, Some(counter.value))),
+                    Err(DatabaseError::NotFound { .. }) => Ok(watch_update(&counter_id, None)),
+                    Err(e) => Err(Status::from(e)),
+                }
+            }
+            Err(RecvError::Closed) => break,
+        };
+
+        if tx.send(update).await.is_err() {
+            // The client went away
+            break;
+        }
+    }
+}
+
 /// Implementation of the HelloService gRPC service with SQLite backend
 pub struct HelloServiceImpl {
     /// Database connection for persistent storage
@@ -42,10 +332,56 @@ impl HelloServiceImpl {
     pub fn new(db: Arc<Database>) -> Self {
         Self { db }
     }
+
+    /// Reads a stream of increments, committing them in batches
+    ///
+    /// `applied_increments` counts the increments committed so far, which is
+    /// still accurate if the stream fails part way through.
+    async fn apply_increment_stream(
+        &self,
+        stream: &mut Streaming<StreamIncrementsRequest>,
+        applied_increments: &mut i64,
+        final_values: &mut BTreeMap<String, i64>,
+    ) -> Result<(), Status> {
+        let mut batch = Vec::with_capacity(INCREMENT_BATCH_SIZE);
+
+        while let Some(message) = stream.message().await? {
+            let counter_id = resolve_counter_id(&message.counter_id)?;
+            batch.push((counter_id.to_string(), widened(message.amount, message.amount_int32)));
+
+            if batch.len() >= INCREMENT_BATCH_SIZE {
+                self.flush_increments(&mut batch, applied_increments, final_values).await?;
+            }
+        }
+        self.flush_increments(&mut batch, applied_increments, final_values).await
+    }
+
+    /// Commits a batch of streamed increments and records the resulting values
+    async fn flush_increments(
+        &self,
+        batch: &mut Vec<(String, i64)>,
+        applied_increments: &mut i64,
+        final_values: &mut BTreeMap<String, i64>,
+    ) -> Result<(), Status> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+
+        let values = self.db.increment_counters(batch)
+            .await?;
+
+        *applied_increments += batch.len() as i64;
+        for ((counter_id, _), value) in batch.drain(..).zip(values) {
+            final_values.insert(counter_id, value.value);
+        }
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
 impl HelloService for HelloServiceImpl {
+    type WatchCounterStream = ReceiverStream<Result<WatchCounterResponse, Status>>;
+
     /// Handles the SayHello RPC method
     async fn say_hello(
         &self,
@@ -66,49 +402,542 @@ impl HelloService for HelloServiceImpl {
         &self,
         request: Request<IncrementCounterRequest>,
     ) -> Result<Response<IncrementCounterResponse>, Status> {
-        let increment_by = request.into_inner().increment_by;
-        println!("Incrementing counter by: {}", increment_by);
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        let increment_by = widened(request.increment_by, request.increment_by_int32);
+        println!("Incrementing counter '{}' by: {}", counter_id, increment_by);
         
-        // Increment the counter in the database
-        let new_value = self.db.increment_counter(MAIN_COUNTER_ID, increment_by)
-            .await
-            .map_err(|e| {
-                eprintln!("Database error: {:?}", e);
-                Status::internal(format!("Database error: {}", e))
-            })?;
+        // Increment the counter in the database, at most once per idempotency key
+        let reason = request.reason.as_deref();
+        let expires_at = expiry_millis(request.expires_at.as_ref())?;
+        let new_value = match request.idempotency_key.as_deref() {
+            Some(key) => {
+                validate_idempotency_key(key)?;
+                self.db.increment_counter_idempotent(counter_id, increment_by, reason, expires_at, key).await?
+            }
+            None => self.db.increment_counter_with_reason(counter_id, increment_by, reason, expires_at).await?,
+        };
         
-        println!("Counter incremented, new value: {}", new_value);
+        println!("Counter incremented, new value: {} (version {})", new_value.value, new_value.version);
 
         // Fetch counter stats if available
-        if let Ok(Some((_, total_increments, avg_increment, highest))) = 
-            self.db.get_counter_stats(MAIN_COUNTER_ID).await {
+        if let Ok(Some(stats)) = self.db.get_counter_stats(counter_id).await {
             println!(
-                "Counter stats: increments={}, avg={:.2}, highest={}", 
-                total_increments, avg_increment, highest
+                "Counter stats: increments={}, avg={:.2}, highest={}, rate={:.2}/min", 
+                stats.total_increments, stats.average_increment, stats.highest_value, stats.increments_per_minute
             );
         }
 
-        Ok(Response::new(IncrementCounterResponse { value: new_value }))
+        Ok(Response::new(new_value.into()))
     }
 
     /// Handles the GetCounter RPC method
     async fn get_counter(
         &self,
-        _request: Request<GetCounterRequest>,  // Fixed unused variable warning with underscore
+        request: Request<GetCounterRequest>,
     ) -> Result<Response<GetCounterResponse>, Status> {
-        println!("Getting counter value");
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        println!("Getting value of counter '{}'", counter_id);
         
-        // Get the counter from the database
-        let value = self.db.get_counter(MAIN_COUNTER_ID)
-            .await
-            .map_err(|e| {
-                eprintln!("Database error: {:?}", e);
-                Status::internal(format!("Database error: {}", e))
-            })?;
+        if let Some(as_of) = &request.as_of {
+            if request.create_if_missing {
+                return Err(Status::invalid_argument("as_of cannot be combined with create_if_missing"));
+            }
+            let value = self.db.get_counter_as_of(counter_id, timestamp_millis(as_of)?).await?;
+            println!("Counter value as of {}s: {}", as_of.seconds, value);
+            let response = GetCounterResponse { value, value_int32: clamp_int32(value), version: 0, expires_at: None };
+            return Ok(Response::new(response));
+        }
+
+        // Get the counter from the database, only creating it if asked to
+        let current = if request.create_if_missing {
+            self.db.get_or_create_counter(counter_id).await
+        } else {
+            self.db.get_counter(counter_id).await
+        }?;
         
-        println!("Current counter value: {}", value);
+        println!("Current counter value: {} (version {})", current.value, current.version);
+
+        Ok(Response::new(current.into()))
+    }
+
+    /// Handles the ListCounters RPC method
+    async fn list_counters(
+        &self,
+        request: Request<ListCountersRequest>,
+    ) -> Result<Response<ListCountersResponse>, Status> {
+        let request = request.into_inner();
+        validate_prefix(&request.prefix)?;
+        let order = counter_order(request.order_by)?;
+        let cursor = decode_list_token(&request)?;
+        let page_size = pagination::page_size(request.page_size);
+        println!("Listing counters with prefix '{}' (page size {})", request.prefix, page_size);
+
+        // Fetch one extra row to find out whether another page follows
+        let mut counters = self.db
+            .list_counters_page(&request.prefix, order, request.descending, cursor.as_ref(), page_size + 1)
+            .await?;
+
+        let next_page_token = if counters.len() > page_size as usize {
+            counters.truncate(page_size as usize);
+            counters
+                .last()
+                .map(|last| encode_list_token(&request, &CounterCursor::from(last)))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        let counters = counters.into_iter().map(CounterEntry::from).collect();
+
+        Ok(Response::new(ListCountersResponse { counters, next_page_token }))
+    }
+
+    /// Handles the GetCounterStats RPC method
+    async fn get_counter_stats(
+        &self,
+        request: Request<GetCounterStatsRequest>,
+    ) -> Result<Response<GetCounterStatsResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        println!("Getting statistics of counter '{}'", counter_id);
+
+        let stats = self.db.get_counter_stats(counter_id)
+            .await?
+            .ok_or_else(|| Status::not_found(format!("counter '{}' not found", counter_id)))?;
+
+        Ok(Response::new(stats.into()))
+    }
+
+    /// Handles the WatchCounter RPC method
+    async fn watch_counter(
+        &self,
+        request: Request<WatchCounterRequest>,
+    ) -> Result<Response<Self::WatchCounterStream>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?.to_string();
+        println!("Watching counter '{}'", counter_id);
+
+        // Subscribe before reading so no change slips in between
+        let changes = self.db.subscribe();
+        let current = self.db.get_counter(&counter_id)
+            .await?;
+
+        let (tx, rx) = mpsc::channel(WATCH_STREAM_BUFFER);
+        tx.send(Ok(watch_update(&counter_id, Some(current.value))))
+            .await
+            .map_err(|_| Status::internal("watch stream closed"))?;
+        tokio::spawn(forward_counter_changes(self.db.clone(), counter_id, changes, tx));
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    /// Handles the StreamIncrements RPC method
+    async fn stream_increments(
+        &self,
+        request: Request<Streaming<StreamIncrementsRequest>>,
+    ) -> Result<Response<StreamIncrementsResponse>, Status> {
+        let mut stream = request.into_inner();
+        let mut final_values = BTreeMap::new();
+        let mut applied_increments = 0;
+
+        let applied = self
+            .apply_increment_stream(&mut stream, &mut applied_increments, &mut final_values)
+            .await;
+        if let Err(mut status) = applied {
+            // Batches committed before the failure stay applied; report how many
+            status
+                .metadata_mut()
+                .insert(APPLIED_INCREMENTS_METADATA, applied_increments.into());
+            return Err(status);
+        }
+
+        println!(
+            "Applied {} streamed increments to {} counters",
+            applied_increments,
+            final_values.len()
+        );
+
+        let counters = final_values
+            .into_iter()
+            .map(|(counter_id, value)| CounterEntry { counter_id, value, value_int32: clamp_int32(value) })
+            .collect();
+
+        Ok(Response::new(StreamIncrementsResponse { applied_increments, counters }))
+    }
+
+    /// Handles the ApplyBatch RPC method
+    async fn apply_batch(
+        &self,
+        request: Request<ApplyBatchRequest>,
+    ) -> Result<Response<ApplyBatchResponse>, Status> {
+        let request = request.into_inner();
+        if request.operations.is_empty() {
+            return Err(Status::invalid_argument("operations must not be empty"));
+        }
+        if request.operations.len() > MAX_BATCH_OPERATIONS {
+            return Err(Status::invalid_argument(format!(
+                "at most {} operations may be applied in one batch",
+                MAX_BATCH_OPERATIONS
+            )));
+        }
+
+        let operations = request.operations
+            .into_iter()
+            .map(counter_operation)
+            .collect::<Result<Vec<_>, _>>()?;
+        println!("Applying a batch of {} counter operations", operations.len());
+
+        let results = self.db.apply_batch(&operations)
+            .await?;
+
+        let results = operations
+            .iter()
+            .zip(results)
+            .map(|(operation, result)| operation_result(operation, result))
+            .collect();
+
+        Ok(Response::new(ApplyBatchResponse { results }))
+    }
+
+    /// Handles the SetOverflowPolicy RPC method
+    async fn set_overflow_policy(
+        &self,
+        request: Request<SetOverflowPolicyRequest>,
+    ) -> Result<Response<SetOverflowPolicyResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        let policy = overflow_policy(request.policy)?;
+        println!("Setting overflow policy of counter '{}' to {}", counter_id, policy.as_str());
+
+        let updated = self.db.set_overflow_policy(counter_id, policy)
+            .await?;
+        if !updated {
+            return Err(Status::not_found(format!("counter '{}' not found", counter_id)));
+        }
+
+        Ok(Response::new(SetOverflowPolicyResponse {}))
+    }
+
+    /// Handles the SetQuota RPC method
+    async fn set_quota(
+        &self,
+        request: Request<SetQuotaRequest>,
+    ) -> Result<Response<SetQuotaResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        let quota = CounterQuota {
+            limit: request.limit,
+            period: quota_period(request.period)?,
+        };
+        if quota.period.is_some() && quota.limit.is_none() {
+            return Err(Status::invalid_argument("a quota period requires a limit"));
+        }
+        println!("Setting quota of counter '{}' to {:?}", counter_id, quota);
+
+        let updated = self.db.set_quota(counter_id, quota).await?;
+        if !updated {
+            return Err(Status::not_found(format!("counter '{}' not found", counter_id)));
+        }
+
+        Ok(Response::new(SetQuotaResponse {}))
+    }
+
+    /// Handles the CreateCounter RPC method
+    async fn create_counter(
+        &self,
+        request: Request<CreateCounterRequest>,
+    ) -> Result<Response<CreateCounterResponse>, Status> {
+        let request = request.into_inner();
+        if request.counter_id.is_empty() {
+            return Err(Status::invalid_argument("counter_id must not be empty"));
+        }
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        let bounds = CounterBounds {
+            min_value: request.min_value,
+            max_value: request.max_value,
+        };
+        if !bounds.is_valid() {
+            return Err(Status::invalid_argument("min_value must not be greater than max_value"));
+        }
+        if !bounds.contains(request.initial_value) {
+            return Err(Status::invalid_argument(format!(
+                "initial_value {} must be {}",
+                request.initial_value, bounds
+            )));
+        }
+        let expires_at = expiry_millis(request.expires_at.as_ref())?;
+        println!("Creating counter '{}' ({})", counter_id, bounds);
+
+        let created = self.db
+            .create_counter(counter_id, request.initial_value, request.description.as_deref(), bounds, expires_at)
+            .await?;
+
+        Ok(Response::new(created.into()))
+    }
+
+    /// Handles the DecrementCounter RPC method
+    async fn decrement_counter(
+        &self,
+        request: Request<DecrementCounterRequest>,
+    ) -> Result<Response<DecrementCounterResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        if request.decrement_by <= 0 {
+            return Err(Status::invalid_argument("decrement_by must be positive"));
+        }
+        println!("Decrementing counter '{}' by: {}", counter_id, request.decrement_by);
+
+        let new_value = self.db
+            .increment_counter_with_reason(counter_id, -request.decrement_by, request.reason.as_deref(), None)
+            .await?;
+
+        Ok(Response::new(new_value.into()))
+    }
+
+    /// Handles the CompareAndSet RPC method
+    async fn compare_and_set(
+        &self,
+        request: Request<CompareAndSetRequest>,
+    ) -> Result<Response<CompareAndSetResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        if request.expected_version < 0 {
+            return Err(Status::invalid_argument("expected_version must not be negative"));
+        }
+        let new_value = widened(request.new_value, request.new_value_int32);
+        println!(
+            "Compare-and-set of counter '{}' at version {} to {}",
+            counter_id, request.expected_version, new_value
+        );
+
+        // A version mismatch surfaces as ABORTED via DatabaseError::Conflict
+        let applied = self.db
+            .compare_and_set(counter_id, request.expected_version, new_value)
+            .await?;
+
+        Ok(Response::new(applied.into()))
+    }
+
+    /// Handles the GetCounterHistory RPC method
+    async fn get_counter_history(
+        &self,
+        request: Request<GetCounterHistoryRequest>,
+    ) -> Result<Response<GetCounterHistoryResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        let range = HistoryRange::of(&request)?;
+        let after = decode_history_token(&request.page_token, counter_id, &range)?;
+        let page_size = pagination::page_size(request.page_size);
+        println!("Getting history of counter '{}' (page size {})", counter_id, page_size);
+
+        // Fetch one extra event to find out whether another page follows
+        let mut events = self.db
+            .get_counter_history(counter_id, range.from, range.to, after, page_size + 1)
+            .await?;
+
+        let next_page_token = if events.len() > page_size as usize {
+            events.truncate(page_size as usize);
+            events
+                .last()
+                .map(|last| encode_history_token(counter_id, &range, last.id))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        let events = events.into_iter().map(hello_service::CounterEvent::from).collect();
+
+        Ok(Response::new(GetCounterHistoryResponse { events, next_page_token }))
+    }
+
+    /// Handles the GetCounterSeries RPC method
+    async fn get_counter_series(
+        &self,
+        request: Request<GetCounterSeriesRequest>,
+    ) -> Result<Response<GetCounterSeriesResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        let granularity = granularity(request.granularity)?;
+        let (Some(start_time), Some(end_time)) = (&request.start_time, &request.end_time) else {
+            return Err(Status::invalid_argument("start_time and end_time are required"));
+        };
+        let from = timestamp_millis(start_time)?;
+        let to = timestamp_millis(end_time)?;
+        if from >= to {
+            return Err(Status::invalid_argument("start_time must be before end_time"));
+        }
+        let span = to.saturating_sub(granularity.bucket_start(from));
+        let bucket_count = span.saturating_add(granularity.millis() - 1) / granularity.millis();
+        if bucket_count > MAX_SERIES_BUCKETS {
+            return Err(Status::invalid_argument(format!(
+                "the range covers {} buckets, at most {} are allowed",
+                bucket_count, MAX_SERIES_BUCKETS
+            )));
+        }
+        println!("Getting {:?} series of counter '{}' ({} buckets)", granularity, counter_id, bucket_count);
+
+        let buckets = self.db.get_counter_series(counter_id, granularity, from, to).await?;
+        let buckets = buckets.into_iter().map(hello_service::SeriesBucket::from).collect();
+
+        Ok(Response::new(GetCounterSeriesResponse { buckets }))
+    }
+
+    /// Handles the SetResetSchedule RPC method
+    async fn set_reset_schedule(
+        &self,
+        request: Request<SetResetScheduleRequest>,
+    ) -> Result<Response<SetResetScheduleResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+
+        if request.schedule.is_empty() {
+            println!("Removing reset schedule of counter '{}'", counter_id);
+            // Fails with NOT_FOUND if the counter doesn't exist
+            self.db.get_counter(counter_id).await?;
+            self.db.remove_reset_schedule(counter_id).await?;
+            return Ok(Response::new(SetResetScheduleResponse { next_reset_at: None }));
+        }
+
+        let schedule: CronSchedule = request
+            .schedule
+            .parse()
+            .map_err(|e: schedule::ScheduleError| Status::invalid_argument(e.to_string()))?;
+        if schedule.next_after(now_millis()).is_none() {
+            return Err(Status::invalid_argument(format!("schedule '{}' never fires", schedule)));
+        }
+        println!(
+            "Resetting counter '{}' to {} on schedule '{}'",
+            counter_id, request.reset_value, schedule
+        );
+
+        let stored = self.db
+            .set_reset_schedule(counter_id, &schedule, request.reset_value)
+            .await?
+            .ok_or_else(|| Status::not_found(format!("counter '{}' not found", counter_id)))?;
+
+        Ok(Response::new(SetResetScheduleResponse {
+            next_reset_at: Some(millis_timestamp(stored.next_reset_at)),
+        }))
+    }
+
+    /// Handles the GetCounterPeriods RPC method
+    async fn get_counter_periods(
+        &self,
+        request: Request<GetCounterPeriodsRequest>,
+    ) -> Result<Response<GetCounterPeriodsResponse>, Status> {
+        let request = request.into_inner();
+        let counter_id = resolve_counter_id(&request.counter_id)?;
+        let after = decode_periods_token(&request.page_token, counter_id)?;
+        let page_size = pagination::page_size(request.page_size);
+        println!("Getting periods of counter '{}' (page size {})", counter_id, page_size);
+
+        // Fetch one extra period to find out whether another page follows
+        let mut periods = self.db.get_counter_periods(counter_id, after, page_size + 1).await?;
+
+        let next_page_token = if periods.len() > page_size as usize {
+            periods.truncate(page_size as usize);
+            periods
+                .last()
+                .map(|last| encode_periods_token(counter_id, last.period_end))
+                .unwrap_or_default()
+        } else {
+            String::new()
+        };
+
+        let periods = periods.into_iter().map(hello_service::CounterPeriod::from).collect();
+
+        Ok(Response::new(GetCounterPeriodsResponse { periods, next_page_token }))
+    }
+
+    /// Handles the NextIds RPC method
+    async fn next_ids(
+        &self,
+        request: Request<NextIdsRequest>,
+    ) -> Result<Response<NextIdsResponse>, Status> {
+        let request = request.into_inner();
+        if request.sequence.is_empty() {
+            return Err(Status::invalid_argument("sequence must not be empty"));
+        }
+        if resolve_counter_id(&request.sequence).is_err() {
+            return Err(Status::invalid_argument("sequence must follow the same rules as counter IDs"));
+        }
+        let count = if request.count == 0 { 1 } else { request.count };
+        if !(1..=MAX_ID_BLOCK).contains(&count) {
+            return Err(Status::invalid_argument(format!(
+                "count must be between 1 and {}",
+                MAX_ID_BLOCK
+            )));
+        }
+
+        let block = self.db.next_ids(&request.sequence, count).await?;
+        println!(
+            "Reserved IDs {} to {} of sequence '{}'",
+            block.first,
+            block.first + block.count - 1,
+            request.sequence
+        );
+
+        Ok(Response::new(block.into()))
+    }
+}
+
+/// Implementation of the RateLimitService gRPC service, sharing the counters' database
+pub struct RateLimitServiceImpl {
+    /// Database holding the rate limit state
+    db: Arc<Database>,
+}
+
+impl RateLimitServiceImpl {
+    /// Create a new service instance with a database connection
+    pub fn new(db: Arc<Database>) -> Self {
+        Self { db }
+    }
+}
+
+#[tonic::async_trait]
+impl RateLimitService for RateLimitServiceImpl {
+    /// Handles the TryAcquire RPC method
+    async fn try_acquire(
+        &self,
+        request: Request<TryAcquireRequest>,
+    ) -> Result<Response<TryAcquireResponse>, Status> {
+        let request = request.into_inner();
+        if request.key.is_empty() {
+            return Err(Status::invalid_argument("key must not be empty"));
+        }
+        if resolve_counter_id(&request.key).is_err() {
+            return Err(Status::invalid_argument("key must follow the same rules as counter IDs"));
+        }
+        let permits = if request.permits == 0 { 1 } else { request.permits };
+        if permits < 0 || request.limit <= 0 {
+            return Err(Status::invalid_argument("permits and limit must be positive"));
+        }
+        if permits > request.limit {
+            return Err(Status::invalid_argument("permits must not exceed limit"));
+        }
+        let window = request
+            .window
+            .as_ref()
+            .ok_or_else(|| Status::invalid_argument("window is required"))
+            .and_then(duration_millis)?;
+        if window > MAX_RATE_LIMIT_WINDOW_MILLIS {
+            return Err(Status::invalid_argument("window must be at most 366 days"));
+        }
 
-        Ok(Response::new(GetCounterResponse { value }))
+        let decision = self.db.try_acquire(&request.key, permits, request.limit, window).await?;
+        println!(
+            "Rate limit '{}': {} {} permits ({} per {}ms, retry after {}ms)",
+            request.key,
+            if decision.allowed { "granted" } else { "denied" },
+            permits,
+            request.limit,
+            window,
+            decision.retry_after
+        );
+
+        Ok(Response::new(decision.into()))
     }
 }
 
@@ -119,14 +948,21 @@ async fn main() -> Result<()> {
     
     // Connect to SQLite database
     println!("Connecting to SQLite database...");
-    let db = Database::connect("sqlite:data.db").await?;
+    let mut db = Database::connect("sqlite:data.db").await?;
+
+    // Remember idempotency keys for the configured window, if one is set
+    if let Ok(seconds) = std::env::var(IDEMPOTENCY_RETENTION_ENV) {
+        let seconds: u64 = seconds.parse()?;
+        println!("Remembering idempotency keys for {}s", seconds);
+        db = db.with_idempotency_retention(std::time::Duration::from_secs(seconds));
+    }
 
     // List all existing counters
     match db.list_counters().await {
         Ok(counters) if !counters.is_empty() => {
             println!("Found {} existing counters:", counters.len());
-            for (id, value) in counters {
-                println!("  - {}: {}", id, value);
+            for counter in counters {
+                println!("  - {}: {}", counter.id, counter.value);
             }
         }
         Ok(_) => println!("No existing counters found"),
@@ -137,15 +973,579 @@ async fn main() -> Result<()> {
     let addr: SocketAddr = "[::1]:50052".parse()?;
     
     // Create the service with the database
-    let service = HelloServiceImpl::new(Arc::new(db));
+    let db = Arc::new(db);
+    let service = HelloServiceImpl::new(db.clone());
+    let rate_limits = RateLimitServiceImpl::new(db.clone());
+
+    // Purge expired counters, rate limit state and idempotency keys in the background
+    let reap_interval = match std::env::var(REAP_INTERVAL_ENV) {
+        Ok(seconds) => std::time::Duration::from_secs(seconds.parse()?),
+        Err(_) => DEFAULT_REAP_INTERVAL,
+    };
+    if reap_interval.is_zero() {
+        anyhow::bail!("{} must be at least 1", REAP_INTERVAL_ENV);
+    }
+    println!("Purging expired state every {}s", reap_interval.as_secs());
+    Reaper::new(db.clone(), reap_interval).spawn();
+
+    // Apply reset schedules and quota periods, starting with any resets missed while the server was down
+    println!("Applying scheduled and quota counter resets");
+    ResetScheduler::new(db, DEFAULT_SCHEDULER_TICK).spawn();
 
     println!("HelloService gRPC server starting on {}", addr);
 
     // Start the server
     Server::builder()
         .add_service(HelloServiceServer::new(service))
+        .add_service(RateLimitServiceServer::new(rate_limits))
         .serve(addr)
         .await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hello_service::hello_service_client::HelloServiceClient;
+    use tokio_stream::StreamExt;
+    use tonic::transport::{server::TcpIncoming, Channel};
+
+    #[tokio::test]
+    async fn test_watch_counter_streams_updates() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        db.set_counter("watched", 3).await?;
+
+        let request = Request::new(WatchCounterRequest { counter_id: "watched".into() });
+        let mut stream = service.watch_counter(request).await?.into_inner();
+
+        // The current value arrives first
+        assert_eq!(stream.next().await.unwrap()?, watch_update("watched", Some(3)));
+
+        // Changes to other counters are filtered out
+        db.increment_counter("other", 1).await?;
+        db.increment_counter("watched", 2).await?;
+        db.delete_counter("watched").await?;
+
+        assert_eq!(stream.next().await.unwrap()?, watch_update("watched", Some(5)));
+        assert_eq!(stream.next().await.unwrap()?, watch_update("watched", None));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_apply_batch_reports_deletes_of_missing_counters() -> Result<()> {
+        use hello_service::{counter_operation::Operation, CounterOperation};
+
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        db.set_counter("present", 1).await?;
+
+        let delete = |counter_id: &str, delete: bool| CounterOperation {
+            counter_id: counter_id.into(),
+            operation: Some(Operation::Delete(delete)),
+        };
+        let request = Request::new(ApplyBatchRequest {
+            operations: vec![delete("present", true), delete("missing", true)],
+        });
+        let results = service.apply_batch(request).await?.into_inner().results;
+        let deleted: Vec<bool> = results.iter().map(|r| r.deleted).collect();
+        assert_eq!(deleted, vec![true, false]);
+
+        // `delete: false` is not a delete, and not anything else either
+        let request = Request::new(ApplyBatchRequest { operations: vec![delete("present", false)] });
+        let err = service.apply_batch(request).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_watch_task_ends_when_client_disconnects() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        db.set_counter("quiet", 1).await?;
+
+        let (tx, rx) = mpsc::channel(WATCH_STREAM_BUFFER);
+        let task = tokio::spawn(forward_counter_changes(db.clone(), "quiet".into(), db.subscribe(), tx));
+
+        // The counter never changes, yet the task notices the stream is gone
+        drop(rx);
+        tokio::time::timeout(std::time::Duration::from_secs(5), task).await??;
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_lagged_watcher_reads_latest_value() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        db.set_counter("busy", 7).await?;
+
+        // A channel too small for the changes sent makes the watcher lag
+        let (changes, receiver) = broadcast::channel(1);
+        let (tx, mut rx) = mpsc::channel(WATCH_STREAM_BUFFER);
+        tokio::spawn(forward_counter_changes(db.clone(), "busy".into(), receiver, tx));
+        for value in 1..=3 {
+            changes.send(CounterChange { id: "busy".into(), value: Some(value) })?;
+        }
+
+        // The skipped changes are replaced by the value stored now, then the last change follows
+        let update = rx.recv().await.unwrap()?;
+        assert_eq!((update.value, update.deleted), (7, false));
+        assert_eq!(rx.recv().await.unwrap()?.value, 3);
+
+        // A counter that expired while the watcher lagged is reported as deleted
+        db.increment_counter_with_reason("busy", 0, None, Some(now_millis() - 1)).await?;
+        for value in 4..=5 {
+            changes.send(CounterChange { id: "busy".into(), value: Some(value) })?;
+        }
+        let update = rx.recv().await.unwrap()?;
+        assert_eq!((update.value, update.deleted), (0, true));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_overflow_maps_to_out_of_range() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        db.set_counter("limit", i64::MAX).await?;
+
+        let request = Request::new(IncrementCounterRequest {
+            increment_by: 1,
+            increment_by_int32: 0,
+            counter_id: "limit".into(),
+            reason: None,
+            idempotency_key: None,
+            expires_at: None,
+        });
+        let err = service.increment_counter(request).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::OutOfRange);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_retried_increment_is_applied_once() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        let request = |key: &str| Request::new(IncrementCounterRequest {
+            increment_by: 3,
+            increment_by_int32: 0,
+            counter_id: "retried".into(),
+            reason: None,
+            idempotency_key: Some(key.to_string()),
+            expires_at: None,
+        });
+
+        let first = service.increment_counter(request("attempt")).await?.into_inner();
+        let retry = service.increment_counter(request("attempt")).await?.into_inner();
+        assert_eq!((retry.value, retry.version), (first.value, first.version));
+        assert_eq!(db.get_counter("retried").await?.value, 3);
+
+        let err = service.increment_counter(request("")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_increment_past_quota_is_resource_exhausted() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        db.set_counter("api_calls", 0).await?;
+
+        let request = Request::new(SetQuotaRequest {
+            counter_id: "api_calls".into(),
+            limit: Some(1),
+            period: hello_service::QuotaPeriod::Day as i32,
+        });
+        service.set_quota(request).await?;
+
+        let increment = || Request::new(IncrementCounterRequest {
+            increment_by: 1,
+            increment_by_int32: 0,
+            counter_id: "api_calls".into(),
+            reason: None,
+            idempotency_key: None,
+            expires_at: None,
+        });
+        service.increment_counter(increment()).await?;
+        let err = service.increment_counter(increment()).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::ResourceExhausted);
+        assert!(!err.details().is_empty());
+
+        let request = Request::new(SetQuotaRequest {
+            counter_id: "api_calls".into(),
+            limit: None,
+            period: hello_service::QuotaPeriod::Week as i32,
+        });
+        let err = service.set_quota(request).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_reset_schedule_archives_periods() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        db.set_counter("daily_signups", 0).await?;
+
+        let schedule_request = |counter_id: &str, schedule: &str| Request::new(SetResetScheduleRequest {
+            counter_id: counter_id.into(),
+            schedule: schedule.into(),
+            reset_value: 0,
+        });
+        for bad in ["every day", "0 0 30 2 *"] {
+            let err = service.set_reset_schedule(schedule_request("daily_signups", bad)).await.unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+        let err = service.set_reset_schedule(schedule_request("missing", "@daily")).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let response = service.set_reset_schedule(schedule_request("daily_signups", "@daily")).await?;
+        assert!(response.into_inner().next_reset_at.is_some());
+
+        // Three days go by with a different final value each
+        for signups in [4, 2, 7] {
+            db.increment_counter("daily_signups", signups).await?;
+            let next_reset_at = db.get_reset_schedule("daily_signups").await?.unwrap().next_reset_at;
+            db.apply_due_resets(next_reset_at).await?;
+        }
+
+        let periods_request = |page_token: String| Request::new(GetCounterPeriodsRequest {
+            counter_id: "daily_signups".into(),
+            page_size: 2,
+            page_token,
+        });
+        let first = service.get_counter_periods(periods_request(String::new())).await?.into_inner();
+        let second = service.get_counter_periods(periods_request(first.next_page_token)).await?.into_inner();
+        let final_values: Vec<i64> = first.periods.iter().chain(&second.periods).map(|p| p.final_value).collect();
+        assert_eq!(final_values, vec![4, 2, 7]);
+        assert!(second.next_page_token.is_empty());
+
+        let response = service.set_reset_schedule(schedule_request("daily_signups", "")).await?;
+        assert!(response.into_inner().next_reset_at.is_none());
+        assert!(db.get_reset_schedule("daily_signups").await?.is_none());
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_next_ids_reserves_contiguous_blocks() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        let next_ids = |sequence: &str, count: i64| Request::new(NextIdsRequest {
+            sequence: sequence.into(),
+            count,
+        });
+
+        let first = service.next_ids(next_ids("orders", 100)).await?.into_inner();
+        assert_eq!((first.first_id, first.count), (1, 100));
+        let second = service.next_ids(next_ids("orders", 0)).await?.into_inner();
+        assert_eq!((second.first_id, second.count), (101, 1));
+
+        for (sequence, count) in [("", 1), ("bad sequence", 1), ("orders", -1), ("orders", MAX_ID_BLOCK + 1)] {
+            let err = service.next_ids(next_ids(sequence, count)).await.unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_decrement_below_min_fails_precondition() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+
+        let request = Request::new(CreateCounterRequest {
+            counter_id: "stock".into(),
+            initial_value: 2,
+            min_value: Some(0),
+            max_value: None,
+            description: None,
+            expires_at: None,
+        });
+        service.create_counter(request).await?;
+
+        let decrement = |amount| Request::new(DecrementCounterRequest {
+            counter_id: "stock".into(),
+            decrement_by: amount,
+            reason: None,
+        });
+        assert_eq!(service.decrement_counter(decrement(2)).await?.into_inner().value, 0);
+        let err = service.decrement_counter(decrement(1)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::FailedPrecondition);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counter_expiry_is_set_and_refreshed() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        let in_a_minute = prost_types::Timestamp { seconds: now_millis() / 1000 + 60, nanos: 0 };
+        let in_an_hour = prost_types::Timestamp { seconds: now_millis() / 1000 + 3600, nanos: 0 };
+
+        let create = |expires_at| Request::new(CreateCounterRequest {
+            counter_id: "failed_logins".into(),
+            initial_value: 0,
+            min_value: None,
+            max_value: None,
+            description: None,
+            expires_at,
+        });
+        let past = prost_types::Timestamp { seconds: 1, nanos: 0 };
+        let err = service.create_counter(create(Some(past))).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        service.create_counter(create(Some(in_a_minute))).await?;
+
+        let increment = |expires_at| Request::new(IncrementCounterRequest {
+            increment_by: 1,
+            increment_by_int32: 0,
+            counter_id: "failed_logins".into(),
+            reason: None,
+            idempotency_key: None,
+            expires_at,
+        });
+        let get = || Request::new(GetCounterRequest {
+            counter_id: "failed_logins".into(),
+            create_if_missing: false,
+            as_of: None,
+        });
+
+        // Increments without an expiry keep the current one, others move it
+        service.increment_counter(increment(None)).await?;
+        assert_eq!(service.get_counter(get()).await?.into_inner().expires_at, Some(in_a_minute));
+        service.increment_counter(increment(Some(in_an_hour))).await?;
+        assert_eq!(service.get_counter(get()).await?.into_inner().expires_at, Some(in_an_hour));
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_try_acquire_reports_retry_after() -> Result<()> {
+        let service = RateLimitServiceImpl::new(Arc::new(Database::connect("sqlite::memory:").await?));
+        let acquire = |permits, limit| Request::new(TryAcquireRequest {
+            key: "api:alice".into(),
+            permits,
+            limit,
+            window: Some(prost_types::Duration { seconds: 3600, nanos: 0 }),
+        });
+
+        let granted = service.try_acquire(acquire(0, 2)).await?.into_inner();
+        assert!(granted.allowed);
+        assert_eq!(granted.remaining, 1);
+        assert_eq!(granted.retry_after, Some(prost_types::Duration::default()));
+
+        service.try_acquire(acquire(1, 2)).await?;
+        let denied = service.try_acquire(acquire(1, 2)).await?.into_inner();
+        assert!(!denied.allowed);
+        let retry_after = denied.retry_after.unwrap();
+        assert!(retry_after.seconds > 1700 && retry_after.seconds <= 1800);
+
+        let err = service.try_acquire(acquire(3, 2)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_get_counter_only_creates_on_request() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+
+        let get = |create_if_missing| Request::new(GetCounterRequest {
+            counter_id: "typo".into(),
+            create_if_missing,
+            as_of: None,
+        });
+        let err = service.get_counter(get(false)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        assert!(db.get_counter_stats("typo").await?.is_none());
+
+        assert_eq!(service.get_counter(get(true)).await?.into_inner().value, 0);
+        assert_eq!(service.get_counter(get(false)).await?.into_inner().value, 0);
+
+        // Point-in-time reads see the value from the history
+        db.increment_counter("typo", 5).await?;
+        let as_of = |as_of, create_if_missing| Request::new(GetCounterRequest {
+            counter_id: "typo".into(),
+            create_if_missing,
+            as_of: Some(as_of),
+        });
+        let far_future = prost_types::Timestamp { seconds: 32_503_680_000, nanos: 0 };
+        assert_eq!(service.get_counter(as_of(far_future, false)).await?.into_inner().value, 5);
+        let epoch = prost_types::Timestamp::default();
+        let err = service.get_counter(as_of(epoch, false)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::NotFound);
+        let err = service.get_counter(as_of(epoch, true)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counter_history_pages() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let service = HelloServiceImpl::new(db.clone());
+        for amount in 1..=3 {
+            let request = IncrementCounterRequest {
+                increment_by: amount,
+                increment_by_int32: 0,
+                counter_id: "audited".into(),
+                reason: Some(format!("batch {}", amount)),
+                idempotency_key: None,
+                expires_at: None,
+            };
+            service.increment_counter(Request::new(request)).await?;
+        }
+
+        let history = |page_token: String| GetCounterHistoryRequest {
+            counter_id: "audited".into(),
+            page_size: 2,
+            page_token,
+            ..Default::default()
+        };
+        let first = service.get_counter_history(Request::new(history(String::new()))).await?.into_inner();
+        let values: Vec<_> = first.events.iter().map(|e| e.value).collect();
+        assert_eq!(values, vec![Some(1), Some(3)]);
+        assert_eq!(first.events[0].reason.as_deref(), Some("batch 1"));
+
+        let second = service
+            .get_counter_history(Request::new(history(first.next_page_token.clone())))
+            .await?
+            .into_inner();
+        let values: Vec<_> = second.events.iter().map(|e| e.value).collect();
+        assert_eq!(values, vec![Some(6)]);
+        assert!(second.next_page_token.is_empty());
+
+        // A token cannot be reused with a different time range
+        let narrowed = GetCounterHistoryRequest {
+            start_time: Some(prost_types::Timestamp { seconds: 1, nanos: 0 }),
+            ..history(first.next_page_token)
+        };
+        let err = service.get_counter_history(Request::new(narrowed)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_counter_series_validates_range() -> Result<()> {
+        let service = HelloServiceImpl::new(Arc::new(Database::connect("sqlite::memory:").await?));
+        let at = |seconds| Some(prost_types::Timestamp { seconds, nanos: 0 });
+        let series = |granularity: hello_service::SeriesGranularity, start_time, end_time| {
+            Request::new(GetCounterSeriesRequest {
+                counter_id: "hits".into(),
+                granularity: granularity as i32,
+                start_time,
+                end_time,
+            })
+        };
+
+        // Partial buckets at either end are included
+        let response = service
+            .get_counter_series(series(hello_service::SeriesGranularity::Hour, at(1_800), at(7_201)))
+            .await?
+            .into_inner();
+        let starts: Vec<_> = response.buckets.iter().map(|b| b.start_time.unwrap().seconds).collect();
+        assert_eq!(starts, vec![0, 3_600, 7_200]);
+
+        let invalid = [
+            series(hello_service::SeriesGranularity::Minute, None, at(60)),
+            series(hello_service::SeriesGranularity::Minute, at(60), at(60)),
+            series(hello_service::SeriesGranularity::Minute, at(0), at(60 * (MAX_SERIES_BUCKETS + 1))),
+        ];
+        for request in invalid {
+            let err = service.get_counter_series(request).await.unwrap_err();
+            assert_eq!(err.code(), tonic::Code::InvalidArgument);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_list_token_is_bound_to_request() {
+        let request = ListCountersRequest {
+            prefix: "orders".into(),
+            order_by: hello_service::CounterSortOrder::Value as i32,
+            descending: true,
+            page_size: 10,
+            page_token: String::new(),
+        };
+        let last = CounterCursor { id: "orders.eu".into(), value: 3 };
+        let token = encode_list_token(&request, &last);
+
+        // The token round-trips for the same request
+        let same = ListCountersRequest { page_token: token.clone(), ..request.clone() };
+        assert_eq!(decode_list_token(&same).unwrap(), Some(last));
+
+        // But is rejected when the prefix or ordering changes
+        let other = ListCountersRequest { page_token: token, prefix: "users".into(), ..request };
+        assert_eq!(decode_list_token(&other).unwrap_err().code(), tonic::Code::InvalidArgument);
+    }
+
+    /// Serves the service on a local port and returns a client connected to it
+    async fn serve(db: Arc<Database>) -> Result<HelloServiceClient<Channel>> {
+        let incoming = TcpIncoming::bind("127.0.0.1:0".parse()?)?;
+        let addr = incoming.local_addr()?;
+        let server = Server::builder()
+            .add_service(HelloServiceServer::new(HelloServiceImpl::new(db)))
+            .serve_with_incoming(incoming);
+        tokio::spawn(server);
+        Ok(HelloServiceClient::new(Channel::from_shared(format!("http://{}", addr))?.connect().await?))
+    }
+
+    #[tokio::test]
+    async fn test_failed_stream_reports_applied_increments() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let mut client = serve(db.clone()).await?;
+
+        // The stream breaks after the first batch has been committed
+        let messages: Vec<_> = (0..INCREMENT_BATCH_SIZE + 100)
+            .map(|i| StreamIncrementsRequest {
+                counter_id: if i == INCREMENT_BATCH_SIZE + 50 { "bad id".into() } else { "streamed".into() },
+                amount: 1,
+                amount_int32: 0,
+            })
+            .collect();
+        let err = client.stream_increments(tokio_stream::iter(messages)).await.unwrap_err();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        // The error says how many increments were committed, matching the database
+        let applied = err.metadata().get(APPLIED_INCREMENTS_METADATA).unwrap().to_str()?;
+        assert_eq!(applied, INCREMENT_BATCH_SIZE.to_string());
+        assert_eq!(db.get_counter("streamed").await?.value, INCREMENT_BATCH_SIZE as i64);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_id_allocator_leases_blocks_from_the_server() -> Result<()> {
+        use agentic_protos::id_allocator::IdAllocator;
+
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let client = serve(db.clone()).await?;
+
+        // IDs run on across blocks, leasing a new one only when the current one is used up
+        let mut allocator = IdAllocator::new(client.clone(), "orders", 3)?;
+        let mut ids = Vec::new();
+        for _ in 0..7 {
+            ids.push(allocator.next_id().await?);
+        }
+        assert_eq!(ids, (1..8).collect::<Vec<_>>());
+        assert_eq!(db.next_ids("orders", 1).await?.first, 10);
+
+        // Another allocator never sees the IDs left in the first one's block
+        let mut other = IdAllocator::new(client.clone(), "orders", 3)?;
+        assert_eq!(other.next_id().await?, 11);
+        assert_eq!(allocator.next_id().await?, 8);
+
+        // Blocks must hold at least one ID
+        let err = IdAllocator::new(client, "orders", 0).err().unwrap();
+        assert_eq!(err.code(), tonic::Code::InvalidArgument);
+
+        Ok(())
+    }
+}
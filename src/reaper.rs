@@ -0,0 +1,111 @@
+//! Background deletion of expired state.
+//!
+//! Expired counters already read as absent through `Database`; the reaper
+//! deletes them periodically so they stop taking up space and watchers
+//! learn that they are gone. The same pass drops the state of rate limit
+//! keys that have fully replenished and expired idempotency keys.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::database::{now_millis, Database};
+use crate::error::Result;
+
+/// How often expired state is purged, by default
+pub const DEFAULT_REAP_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Periodically deletes expired counters, idle rate limit state and expired
+/// idempotency keys
+pub struct Reaper {
+    /// Database the counters are stored in
+    db: Arc<Database>,
+    /// Time between two purges
+    interval: Duration,
+}
+
+impl Reaper {
+    /// Creates a reaper purging `db` every `interval`
+    pub fn new(db: Arc<Database>, interval: Duration) -> Self {
+        Self { db, interval }
+    }
+
+    /// Purges the counters that have expired by `now`, along with idle rate
+    /// limit state and expired idempotency keys
+    ///
+    /// This is one pass of the background task. Tests call it directly with
+    /// a chosen time instead of waiting for the interval to elapse.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the purged counters
+    pub async fn run_once(&self, now: i64) -> Result<Vec<String>> {
+        let purged = self.db.purge_expired_counters(now).await?;
+        if !purged.is_empty() {
+            println!("Purged {} expired counters: {}", purged.len(), purged.join(", "));
+        }
+        let idle = self.db.purge_idle_rate_limits(now).await?;
+        if idle > 0 {
+            println!("Purged the state of {} idle rate limit keys", idle);
+        }
+        let keys = self.db.purge_expired_idempotency_keys(now).await?;
+        if keys > 0 {
+            println!("Purged {} expired idempotency keys", keys);
+        }
+        Ok(purged)
+    }
+
+    /// Starts purging expired state every interval on a background task
+    ///
+    /// Failed passes are logged and retried at the next interval.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(self.interval);
+            loop {
+                ticks.tick().await;
+                if let Err(e) = self.run_once(now_millis()).await {
+                    eprintln!("Failed to purge expired state: {}", e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{CounterBounds, CounterChange, CounterEventKind};
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_run_once_purges_expired_counters() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let reaper = Reaper::new(db.clone(), DEFAULT_REAP_INTERVAL);
+        let now = now_millis();
+
+        db.create_counter("soon", 1, None, CounterBounds::default(), Some(now + 60_000)).await?;
+        db.create_counter("later", 2, None, CounterBounds::default(), Some(now + 120_000)).await?;
+        db.create_counter("forever", 3, None, CounterBounds::default(), None).await?;
+        let mut changes = db.subscribe();
+
+        assert!(reaper.run_once(now).await?.is_empty());
+        assert_eq!(reaper.run_once(now + 60_000).await?, vec!["soon".to_string()]);
+        assert_eq!(reaper.run_once(now + 120_000).await?, vec!["later".to_string()]);
+
+        // Watchers are told about each purged counter
+        let deleted = CounterChange { id: "soon".to_string(), value: None };
+        assert_eq!(changes.recv().await?, deleted);
+
+        let history = db.get_counter_history("soon", None, None, None, 10).await?;
+        let deleted = history.last().unwrap();
+        assert_eq!((deleted.kind, deleted.reason.as_deref()), (CounterEventKind::Delete, Some("expired")));
+        assert_eq!(db.list_counters().await?.iter().filter(|c| c.id != "main_counter").count(), 1);
+
+        Ok(())
+    }
+}
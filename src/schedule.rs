@@ -0,0 +1,274 @@
+//! Cron-like schedules for resetting counters.
+//!
+//! Schedules use the five standard cron fields, evaluated in UTC:
+//!
+//! ```text
+//! minute (0-59)  hour (0-23)  day of month (1-31)  month (1-12)  day of week (0-7)
+//! ```
+//!
+//! Each field is `*`, a number, a range `a-b`, a step `*/n`, `a/n` or `a-b/n`,
+//! or a comma separated list of these. Both 0 and 7 mean Sunday in the day of
+//! week field. As in cron, a day matches if either day field matches when
+//! both are restricted. The shorthands `@hourly`, `@daily` (or `@midnight`),
+//! `@weekly`, `@monthly` and `@yearly` (or `@annually`) are also accepted.
+
+use std::{fmt, str::FromStr};
+
+/// Milliseconds in a minute
+const MINUTE_MILLIS: i64 = 60_000;
+
+/// Milliseconds in a day
+const DAY_MILLIS: i64 = 86_400_000;
+
+/// Number of days searched for the next run of a schedule, about nine years
+///
+/// Long enough to find a February 29 across a century year that is not a
+/// leap year, eight years apart; schedules that do not run within it are
+/// treated as never running.
+const MAX_SEARCH_DAYS: i64 = 366 * 9;
+
+/// Error returned when a schedule cannot be parsed
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("invalid schedule '{schedule}': {reason}")]
+pub struct ScheduleError {
+    /// The schedule as given
+    pub schedule: String,
+    /// What is wrong with it
+    pub reason: String,
+}
+
+/// A parsed cron-like schedule
+///
+/// Each field is kept as a bit set of the values it matches.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CronSchedule {
+    /// The schedule as written, used to store it
+    source: String,
+    /// Matching minutes, bit 0 being minute 0
+    minutes: u64,
+    /// Matching hours, bit 0 being midnight
+    hours: u64,
+    /// Matching days of the month, bit 1 being the first
+    days_of_month: u64,
+    /// Matching months, bit 1 being January
+    months: u64,
+    /// Matching days of the week, bit 0 being Sunday
+    days_of_week: u64,
+    /// Whether the day of month field was restricted (did not start with `*`)
+    day_of_month_restricted: bool,
+    /// Whether the day of week field was restricted (did not start with `*`)
+    day_of_week_restricted: bool,
+}
+
+impl CronSchedule {
+    /// Returns the first time the schedule fires strictly after `millis`
+    ///
+    /// Both times are in milliseconds since the Unix epoch; schedules fire at
+    /// the start of a minute.
+    ///
+    /// # Returns
+    ///
+    /// The next run, or `None` if the schedule does not fire within the next
+    /// `MAX_SEARCH_DAYS` days, about nine years (e.g. on February 30)
+    pub fn next_after(&self, millis: i64) -> Option<i64> {
+        let start = millis.div_euclid(MINUTE_MILLIS).checked_add(1)?.checked_mul(MINUTE_MILLIS)?;
+        let first_day = start.div_euclid(DAY_MILLIS);
+        let mut first_minute = (start - first_day * DAY_MILLIS) / MINUTE_MILLIS;
+
+        for day in first_day..first_day + MAX_SEARCH_DAYS {
+            if self.matches_day(day) {
+                let minute = (first_minute..24 * 60)
+                    .find(|minute| has_bit(self.hours, minute / 60) && has_bit(self.minutes, minute % 60));
+                if let Some(minute) = minute {
+                    return day.checked_mul(DAY_MILLIS)?.checked_add(minute * MINUTE_MILLIS);
+                }
+            }
+            first_minute = 0;
+        }
+        None
+    }
+
+    /// Returns true if the schedule fires on a day counted from 1970-01-01
+    fn matches_day(&self, day: i64) -> bool {
+        let (_, month, day_of_month) = civil_from_days(day);
+        // 1970-01-01 was a Thursday
+        let day_of_week = (day + 4).rem_euclid(7);
+
+        let day_of_month = has_bit(self.days_of_month, i64::from(day_of_month));
+        let day_of_week = has_bit(self.days_of_week, day_of_week);
+        let day_matches = if self.day_of_month_restricted && self.day_of_week_restricted {
+            day_of_month || day_of_week
+        } else {
+            day_of_month && day_of_week
+        };
+        day_matches && has_bit(self.months, i64::from(month))
+    }
+}
+
+impl FromStr for CronSchedule {
+    type Err = ScheduleError;
+
+    fn from_str(schedule: &str) -> Result<Self, Self::Err> {
+        let error = |reason: String| ScheduleError {
+            schedule: schedule.to_string(),
+            reason,
+        };
+
+        let expanded = match schedule.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            other => other,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        let [minutes, hours, days_of_month, months, days_of_week] = fields.as_slice() else {
+            return Err(error(format!("expected 5 fields, found {}", fields.len())));
+        };
+
+        let mut days_of_week_mask = parse_field(days_of_week, 0, 7).map_err(error)?;
+        // 7 is another name for Sunday
+        if has_bit(days_of_week_mask, 7) {
+            days_of_week_mask = (days_of_week_mask & !(1 << 7)) | 1;
+        }
+
+        Ok(CronSchedule {
+            source: schedule.trim().to_string(),
+            minutes: parse_field(minutes, 0, 59).map_err(error)?,
+            hours: parse_field(hours, 0, 23).map_err(error)?,
+            days_of_month: parse_field(days_of_month, 1, 31).map_err(error)?,
+            months: parse_field(months, 1, 12).map_err(error)?,
+            days_of_week: days_of_week_mask,
+            day_of_month_restricted: !days_of_month.starts_with('*'),
+            day_of_week_restricted: !days_of_week.starts_with('*'),
+        })
+    }
+}
+
+impl fmt::Display for CronSchedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+/// Parses one cron field into the bit set of the values it matches
+fn parse_field(field: &str, min: i64, max: i64) -> Result<u64, String> {
+    let number = |value: &str| {
+        value
+            .parse::<i64>()
+            .ok()
+            .filter(|value| (min..=max).contains(value))
+            .ok_or_else(|| format!("'{}' is not a number between {} and {}", value, min, max))
+    };
+
+    let mut mask = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<i64>() {
+                Ok(step) if step > 0 => (range, Some(step)),
+                _ => return Err(format!("'{}' is not a valid step", step)),
+            },
+            None => (part, None),
+        };
+
+        let (first, last) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((first, last)) => (number(first)?, number(last)?),
+            // A single value with a step runs to the end of the field
+            None if step.is_some() => (number(range)?, max),
+            None => {
+                let value = number(range)?;
+                (value, value)
+            }
+        };
+        if first > last {
+            return Err(format!("range '{}' is reversed", range));
+        }
+
+        for value in (first..=last).step_by(step.unwrap_or(1) as usize) {
+            mask |= 1 << value;
+        }
+    }
+    Ok(mask)
+}
+
+/// Returns true if bit `index` of `mask` is set
+fn has_bit(mask: u64, index: i64) -> bool {
+    mask & (1 << index) != 0
+}
+
+/// Converts a day counted from 1970-01-01 into a (year, month, day) date
+///
+/// Uses Howard Hinnant's `civil_from_days` algorithm for the proleptic
+/// Gregorian calendar; months and days start at 1.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    (year, month as u32, day as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 2024-02-29T12:34:56Z, a Thursday
+    const LEAP_DAY: i64 = 1_709_210_096_000;
+
+    fn next(schedule: &str, after: i64) -> Option<i64> {
+        schedule.parse::<CronSchedule>().unwrap().next_after(after)
+    }
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(civil_from_days(LEAP_DAY / DAY_MILLIS), (2024, 2, 29));
+        assert_eq!(civil_from_days(LEAP_DAY / DAY_MILLIS + 1), (2024, 3, 1));
+    }
+
+    #[test]
+    fn test_next_after() {
+        assert_eq!(next("@daily", LEAP_DAY), Some(1_709_251_200_000)); // 2024-03-01T00:00Z
+        assert_eq!(next("@hourly", LEAP_DAY), Some(1_709_211_600_000)); // 13:00
+        assert_eq!(next("*/15 * * * *", LEAP_DAY), Some(1_709_210_700_000)); // 12:45
+        assert_eq!(next("0 9 * * 1-5", LEAP_DAY), Some(1_709_283_600_000)); // Friday 09:00
+        assert_eq!(next("0 0 * * 7", LEAP_DAY), Some(1_709_424_000_000)); // Sunday 2024-03-03
+        assert_eq!(next("@monthly", LEAP_DAY), Some(1_709_251_200_000));
+        assert_eq!(next("0 0 29 2 *", LEAP_DAY), Some(1_835_395_200_000)); // 2028-02-29
+
+        // Runs are strictly after the given time
+        assert_eq!(next("* * * * *", 1_709_251_200_000), Some(1_709_251_260_000));
+
+        // Restricting both day fields matches either: the 13th or any Friday
+        assert_eq!(next("0 0 13 * 5", LEAP_DAY), Some(1_709_251_200_000));
+    }
+
+    #[test]
+    fn test_schedules_that_never_run() {
+        // Dates that don't exist never match
+        for schedule in ["0 0 30 2 *", "0 0 31 4 *", "0 0 31 2,4,6,9,11 *"] {
+            assert_eq!(next(schedule, LEAP_DAY), None, "{}", schedule);
+        }
+
+        // The search still spans the eight years between two February 29s around 2100
+        assert_eq!(next("0 0 29 2 *", 3_981_398_400_000), Some(4_233_686_400_000)); // 2096-03-01 to 2104-02-29
+    }
+
+    #[test]
+    fn test_invalid_schedules() {
+        for schedule in ["", "* * * *", "60 * * * *", "* * 0 * *", "*/0 * * * *", "5-1 * * * *", "a * * * *"] {
+            assert!(schedule.parse::<CronSchedule>().is_err(), "{}", schedule);
+        }
+
+        let schedule: CronSchedule = " 0 0 * * * ".parse().unwrap();
+        assert_eq!(schedule.to_string(), "0 0 * * *");
+    }
+}
@@ -0,0 +1,110 @@
+//! In-process scheduler applying counter reset schedules.
+//!
+//! Reset schedules are stored in SQLite through `Database`, so they survive
+//! restarts. The scheduler checks for due resets every tick, starting as soon
+//! as it is spawned: resets whose time passed while the server was down are
+//! therefore applied at startup, archiving the final value of the period
+//! they ended like any other reset. Each tick also starts the new quota
+//! period of quota counters whose period has ended.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::task::JoinHandle;
+
+use crate::database::{now_millis, Database};
+use crate::error::Result;
+
+/// How often the scheduler checks for due resets, by default
+///
+/// Schedules fire at the start of a minute, so resets are applied at most
+/// this long after they are due.
+pub const DEFAULT_SCHEDULER_TICK: Duration = Duration::from_secs(1);
+
+/// Periodically resets counters whose reset schedule or quota period is due
+pub struct ResetScheduler {
+    /// Database the counters and their schedules are stored in
+    db: Arc<Database>,
+    /// Time between two checks for due resets
+    tick: Duration,
+}
+
+impl ResetScheduler {
+    /// Creates a scheduler checking `db` for due resets every `tick`
+    pub fn new(db: Arc<Database>, tick: Duration) -> Self {
+        Self { db, tick }
+    }
+
+    /// Applies every reset that is due by `now`, scheduled or at the end of
+    /// a quota period
+    ///
+    /// This is one pass of the background task. Tests call it directly with
+    /// a chosen time instead of waiting for a reset to be due.
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - The current time in milliseconds since the Unix epoch
+    ///
+    /// # Returns
+    ///
+    /// The IDs of the counters that were reset on their schedule
+    pub async fn run_once(&self, now: i64) -> Result<Vec<String>> {
+        let reset = self.db.apply_due_resets(now).await?;
+        if !reset.is_empty() {
+            println!("Applied scheduled resets to: {}", reset.join(", "));
+        }
+        let quotas = self.db.reset_quota_periods(now).await?;
+        if !quotas.is_empty() {
+            println!("Started a new quota period for: {}", quotas.join(", "));
+        }
+        Ok(reset)
+    }
+
+    /// Starts applying due resets every tick on a background task
+    ///
+    /// The first pass runs immediately, catching up on missed resets. Failed
+    /// passes are logged and retried at the next tick.
+    pub fn spawn(self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(self.tick);
+            loop {
+                ticks.tick().await;
+                if let Err(e) = self.run_once(now_millis()).await {
+                    eprintln!("Failed to apply scheduled resets: {}", e);
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::{CounterQuota, QuotaPeriod};
+    use crate::schedule::CronSchedule;
+    use anyhow::Result;
+
+    #[tokio::test]
+    async fn test_run_once_catches_up_on_missed_resets() -> Result<()> {
+        let db = Arc::new(Database::connect("sqlite::memory:").await?);
+        let scheduler = ResetScheduler::new(db.clone(), DEFAULT_SCHEDULER_TICK);
+        db.set_counter("hourly", 7).await?;
+        db.set_counter("yearly", 9).await?;
+        let hourly = db.set_reset_schedule("hourly", &"@hourly".parse::<CronSchedule>()?, 0).await?.unwrap();
+        db.set_reset_schedule("yearly", &"@yearly".parse::<CronSchedule>()?, 0).await?;
+        db.set_counter("daily_quota", 4).await?;
+        db.set_quota("daily_quota", CounterQuota { limit: Some(10), period: Some(QuotaPeriod::Day) }).await?;
+
+        // A server coming back a day later resets only the counters that were due
+        let now = hourly.next_reset_at + 86_400_000;
+        assert_eq!(scheduler.run_once(now).await?, vec!["hourly".to_string()]);
+        assert_eq!(db.get_counter("hourly").await?.value, 0);
+        assert_eq!(db.get_counter("yearly").await?.value, 9);
+        assert_eq!(db.get_counter_periods("hourly", None, 10).await?[0].final_value, 7);
+
+        // Quota counters start their new period on the same pass
+        assert_eq!(db.get_counter("daily_quota").await?.value, 0);
+
+        Ok(())
+    }
+}